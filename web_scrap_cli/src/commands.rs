@@ -0,0 +1,163 @@
+use std::io::{self, IsTerminal};
+
+use super::config::{parse_csv_char, DbArgs, ExportArgs, ImportArgs, PruneArgs, QueryArgs, ScrapeArgs, SearchArgs, StatsArgs, StorageSettings};
+use super::db_utils::{check_table_name, create_fts_index, open_db, open_db_file, open_existing_db};
+use super::errors::{Result, ScrapeError};
+use super::export::{export_records, ExportFormat};
+use super::file_utils::get_timestamp_now;
+use super::filters::ContentFilter;
+use super::import::{csv_files, import_csv_file, FileImport};
+use super::migrations::{applied_migrations, check_schema, latest_version, migrate, record_tables, schema_version, MIGRATIONS};
+use super::query::{ignore_broken_pipe, print_rows, RecordQuery};
+use super::retention::{prune as prune_tables, selector_tables};
+use super::scrap_utils::{get_host_from_url, SelectorSpec};
+use super::stats::db_stats;
+
+/// `stats` : the stats printed at the end of a scrape, for an existing database
+pub fn stats(args: &StatsArgs) -> Result<()>{
+    let conn = open_existing_db(&args.db, false)?;
+    return ignore_broken_pipe(db_stats(&conn, &args.db)?.write(&mut io::stdout(), args.format));
+}
+
+/// `query` : prints the records of a table matching the filters
+pub fn query(args: &QueryArgs) -> Result<()>{
+    let record_query = args.build_query()?;
+    let conn = open_existing_db(&args.db, false)?;
+    let records = record_query.run(&conn, &args.table)?;
+    return print_rows(&records, args.format);
+}
+
+/// `search` : full-text search, the index can be created on the way
+pub fn search(args: &SearchArgs) -> Result<()>{
+    let search_query = args.build_query(io::stdout().is_terminal())?;
+    let conn = open_existing_db(&args.db, args.create_index)?;
+    if args.create_index {
+        create_fts_index(&conn, &args.table)?;
+    }
+    let hits = search_query.run(&conn, &args.table)?;
+    return print_rows(&hits, args.format);
+}
+
+/// `export` : writes the records of a table matching the filters, oldest first
+pub fn export(args: &ExportArgs) -> Result<()>{
+    let query = RecordQuery{descending: false, ..args.filter.build_query()?};
+    let format = args.format.unwrap_or_else(|| ExportFormat::from_path(&args.output));
+    let conn = open_existing_db(&args.db, false)?;
+    let count = export_records(&conn, &args.table, &query, format, &args.output)?;
+    // stdout may carry the records
+    eprintln!("{} records exported to '{}' as {}", count, args.output, format!("{:?}", format).to_lowercase());
+    return Ok(());
+}
+
+/// Invalid rows printed for each file, the other ones are only counted
+const MAX_PRINTED_INVALID_ROWS: usize = 10;
+
+/// `import` : inserts the records of csv files, each file is imported in its own transaction
+pub fn import(args: &ImportArgs) -> Result<()>{
+    let delimiter = parse_csv_char(&args.delimiter, "delimiter")?;
+    let quote = parse_csv_char(&args.quote, "quote")?;
+    if delimiter == quote {
+        return Err(ScrapeError::Config(String::from("the csv delimiter and quote must be different")));
+    }
+    check_table_name(&args.table)?;
+    let files = csv_files(&args.paths)?;
+    let mut conn = open_db(&args.db, &StorageSettings::default())?;
+    let mut total = FileImport::default();
+    let mut failed_files = 0;
+    for path in &files {
+        let report = match import_csv_file(&mut conn, &args.table, path, delimiter, quote) {
+            Ok(report) => report,
+            Err(e) => {
+                println!("'{}' : not imported, {}", path.display(), e);
+                failed_files += 1;
+                continue;
+            }
+        };
+        println!("'{}' : {} imported, {} duplicates, {} invalid", path.display(), report.imported, report.duplicates, report.invalid.len());
+        for (line, reason) in report.invalid.iter().take(MAX_PRINTED_INVALID_ROWS) {
+            println!("  - line {} : {}", line, reason);
+        }
+        if report.invalid.len() > MAX_PRINTED_INVALID_ROWS {
+            println!("  - and {} more", report.invalid.len() - MAX_PRINTED_INVALID_ROWS);
+        }
+        total.imported += report.imported;
+        total.duplicates += report.duplicates;
+        total.invalid.extend(report.invalid);
+    }
+    println!("{} records imported into '{}', {} skipped as duplicates, {} skipped as invalid, from {} file(s)",
+             total.imported, args.table, total.duplicates, total.invalid.len(), files.len() - failed_files);
+    if failed_files > 0 {
+        return Err(ScrapeError::Io(io::Error::new(io::ErrorKind::InvalidData, format!("{} file(s) couldn't be imported", failed_files))));
+    }
+    return Ok(());
+}
+
+/// `prune` : deletes the records left out by the retention rules, a dry run only counts them
+pub fn prune(args: &PruneArgs) -> Result<()>{
+    let (db, settings) = args.build_settings()?;
+    // a dry run deletes in a transaction rolled back at the end, without migrating the database
+    let mut conn = if args.dry_run { open_db_file(&db, true)? } else { open_existing_db(&db, true)? };
+    if args.dry_run {
+        check_schema(&conn)?;
+    }
+    let tables = selector_tables(&conn, &args.table)?;
+    let report = prune_tables(&mut conn, &db, &tables, &settings, get_timestamp_now(), args.dry_run)?;
+    return report.write(&mut io::stdout(), args.dry_run);
+}
+
+/// `validate` : builds the config like `scrape` would, then checks everything a request would need
+/// except the network. The yaml values are already checked while loading, not the cli ones.
+pub fn validate(args: &ScrapeArgs) -> Result<()>{
+    let config = args.build_config()?;
+    for warning in &config.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    for url_selector in &config.url_selectors {
+        get_host_from_url(&url_selector.url)?;
+        SelectorSpec::parse(&url_selector.selector)?;
+        ContentFilter::new(&config.filter_settings_for(url_selector))?;
+    }
+    println!("Config is valid : {} url selector pairs for {} pages", config.url_selectors.len(), config.url_selector_groups().len());
+    return Ok(());
+}
+
+/// `db migrate` : upgrades the schema of an existing database
+pub fn db_migrate(args: &DbArgs) -> Result<()>{
+    let mut conn = open_db_file(&args.db, true)?;
+    let version = schema_version(&conn)?;
+    let applied = migrate(&mut conn)?;
+    if applied.is_empty() {
+        println!("Database '{}' is up to date, schema version {}", args.db, version);
+        return Ok(());
+    }
+    for migration in &applied {
+        println!("Applied migration {} : {}", migration.version, migration.description);
+    }
+    println!("Database '{}' migrated from schema version {} to {}", args.db, version, latest_version());
+    return Ok(());
+}
+
+/// `db status` : schema version and migrations, works on a database of any version
+pub fn db_status(args: &DbArgs) -> Result<()>{
+    let conn = open_db_file(&args.db, false)?;
+    let version = schema_version(&conn)?;
+    println!("Schema version {}, this binary supports up to {}", version, latest_version());
+    if version > latest_version() {
+        println!("The database is newer than this binary, upgrade web_scrap_cli to use it");
+    }
+    let applied = applied_migrations(&conn)?;
+    for migration in MIGRATIONS {
+        match applied.iter().find(|(v, _)| *v == migration.version) {
+            Some((_, applied_at)) => println!("  {:>3}  applied at {}  {}", migration.version, applied_at, migration.description),
+            None => println!("  {:>3}  pending                {}", migration.version, migration.description),
+        }
+    }
+    let tables = record_tables(&conn)?;
+    if !tables.is_empty() {
+        println!("Record tables :");
+        for (name, kind) in tables {
+            println!("  - {} ({})", name, kind);
+        }
+    }
+    return Ok(());
+}
@@ -1,18 +1,312 @@
+use std::collections::{BTreeMap, HashMap};
 use std::env;
-use clap::Parser;
-use std::error::Error;
-use yaml_rust::YamlLoader;
+use std::time::Duration;
+use clap::{Parser, Subcommand};
+use regex::Regex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use log::*;
 
 use  super::file_utils;
+use super::config_file::ConfigFile;
+use super::export::ExportFormat;
+use super::stats::ReportFormat;
+use super::query::{civil_from_timestamp, parse_time, OrderBy, OutputFormat, RecordQuery};
+use super::search::SearchQuery;
+use super::db_utils::check_table_name;
+use super::scrap_utils::{get_host_from_url, FieldSpec};
+use super::errors::{Result, ScrapeError};
+
+pub const DEFAULT_TABLE: &str = "selector_record";
 
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 pub struct Args {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    /// Arguments of the `scrape` command, used when no subcommand is given
+    #[clap(flatten)]
+    pub scrape: ScrapeArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    #[clap(about="Scrap the configured urls and save the records, the default command")]
+    Scrape(ScrapeArgs),
+
+    #[clap(about="Print the records, hosts, selectors, time range and records per day of each table of an existing database")]
+    Stats(StatsArgs),
+
+    #[clap(about="Print the selector records saved in a table, filtered by host, url, selector, time or content")]
+    Query(QueryArgs),
+
+    #[clap(about="Full-text search in the content of a selector record table, best matches first")]
+    Search(SearchArgs),
+
+    #[clap(about="Write the selector records of a table matching the filters to a csv, JSON Lines or Parquet file")]
+    Export(ExportArgs),
+
+    #[clap(about="Import the selector records of csv files written by --to-csv, the rows already in the table are skipped")]
+    Import(ImportArgs),
+
+    #[clap(about="Delete the old records of the selector record tables following retention rules, from the options or a yaml 'retention' section")]
+    Prune(PruneArgs),
+
+    #[clap(about="Check the config, its urls, selectors and filters without any network access")]
+    Validate(ScrapeArgs),
+
+    #[clap(subcommand, about="Database schema version and migrations")]
+    Db(DbCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DbCommand {
+    #[clap(about="Apply the pending schema migrations, the other commands writing to the database also do it")]
+    Migrate(DbArgs),
+
+    #[clap(about="Print the schema version, the applied and pending migrations and the record tables")]
+    Status(DbArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DbArgs {
+    #[clap(short, long, help="Database file")]
+    pub db: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    #[clap(short, long, help="Database file")]
+    pub db: String,
+
+    #[clap(short, long, arg_enum, default_value="text", help="Output format")]
+    pub format: ReportFormat,
+}
+
+/// Filters of the selector records shared by `query` and `export`
+#[derive(clap::Args, Debug)]
+pub struct RecordFilterArgs {
+    #[clap(long, help="Only the records of this host, like www.cnews.fr")]
+    pub host: Option<String>,
+
+    #[clap(short, long, value_name="PATTERN", help="Only the urls matching this glob pattern, like 'https://www.cnews.fr/*'")]
+    pub url: Option<String>,
+
+    #[clap(short, long, help="Only the records of this selector")]
+    pub selector: Option<String>,
+
+    #[clap(long, value_name="TIME", help="Only the records saved since a duration (24h, 30m, 7d), a unix timestamp or a YYYY-MM-DD date")]
+    pub since: Option<String>,
+
+    #[clap(long, value_name="TIME", help="Only the records saved until a duration (24h, 30m, 7d), a unix timestamp or a YYYY-MM-DD date")]
+    pub until: Option<String>,
+
+    #[clap(long, value_name="TEXT", help="Only the records whose content contains this text")]
+    pub contains: Option<String>,
+
+    #[clap(long, value_name="REGEX", help="Only the records whose content matches this regex")]
+    pub regex: Option<String>,
+}
+
+impl RecordFilterArgs{
+    /// The query of the filters, newest records first and without limit
+    pub fn build_query(&self) -> Result<RecordQuery>{
+        let now = file_utils::get_timestamp_now();
+        let content_regex = match &self.regex {
+            Some(re) => Some(Regex::new(re).map_err(|e| ScrapeError::Config(format!("invalid regex '{}': {}", re, e)))?),
+            None => None
+        };
+        return Ok(RecordQuery{
+            host: self.host.clone(),
+            url_pattern: self.url.clone(),
+            selector: self.selector.clone(),
+            since: self.since.as_deref().map(|v| parse_time(v, now)).transpose()?,
+            until: self.until.as_deref().map(|v| parse_time(v, now)).transpose()?,
+            contains: self.contains.clone(),
+            content_regex,
+            ..RecordQuery::default()
+        });
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct QueryArgs {
+    #[clap(short, long, help="Database to read")]
+    pub db: String,
+
+    #[clap(short, long, default_value=DEFAULT_TABLE, help="Table to read")]
+    pub table: String,
+
+    #[clap(flatten)]
+    pub filter: RecordFilterArgs,
+
+    #[clap(long, arg_enum, default_value="timestamp", help="Column used to sort the records")]
+    pub order_by: OrderBy,
+
+    #[clap(long, help="Sort in ascending order, newest records first otherwise")]
+    pub asc: bool,
+
+    #[clap(short, long, help="Maximum number of records to print")]
+    pub limit: Option<usize>,
+
+    #[clap(short, long, arg_enum, default_value="table", help="Output format")]
+    pub format: OutputFormat,
+}
+
+impl QueryArgs{
+    pub fn build_query(&self) -> Result<RecordQuery>{
+        return Ok(RecordQuery{
+            order_by: self.order_by,
+            descending: !self.asc,
+            limit: self.limit,
+            ..self.filter.build_query()?
+        });
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SearchArgs {
+    #[clap(help="Words to search, FTS5 syntax : \"a phrase\", prefix*, AND, OR, NOT")]
+    pub terms: String,
+
+    #[clap(short, long, help="Database to read")]
+    pub db: String,
+
+    #[clap(short, long, default_value=DEFAULT_TABLE, help="Table to read")]
+    pub table: String,
+
+    #[clap(long, help="Only the records of this host, like www.cnews.fr")]
+    pub host: Option<String>,
+
+    #[clap(long, value_name="TIME", help="Only the records saved since a duration (24h, 30m, 7d), a unix timestamp or a YYYY-MM-DD date")]
+    pub since: Option<String>,
+
+    #[clap(long, value_name="TIME", help="Only the records saved until a duration (24h, 30m, 7d), a unix timestamp or a YYYY-MM-DD date")]
+    pub until: Option<String>,
+
+    #[clap(short, long, default_value="20", help="Maximum number of records to print")]
+    pub limit: usize,
+
+    #[clap(short, long, arg_enum, default_value="table", help="Output format")]
+    pub format: OutputFormat,
+
+    #[clap(long, help="Create the full-text index of the table first if it doesn't exist")]
+    pub create_index: bool,
+}
+
+impl SearchArgs{
+    /// Matches are highlighted in bold in a terminal, between brackets otherwise
+    pub fn build_query(&self, terminal: bool) -> Result<SearchQuery>{
+        let now = file_utils::get_timestamp_now();
+        let highlight = match (terminal, self.format) {
+            (true, OutputFormat::Table) => (String::from("\x1b[1m"), String::from("\x1b[0m")),
+            _ => (String::from("["), String::from("]"))
+        };
+        return Ok(SearchQuery{
+            terms: self.terms.clone(),
+            host: self.host.clone(),
+            since: self.since.as_deref().map(|v| parse_time(v, now)).transpose()?,
+            until: self.until.as_deref().map(|v| parse_time(v, now)).transpose()?,
+            limit: self.limit,
+            highlight
+        });
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    #[clap(short, long, help="Database to read")]
+    pub db: String,
+
+    #[clap(short, long, default_value=DEFAULT_TABLE, help="Table to read")]
+    pub table: String,
+
+    #[clap(flatten)]
+    pub filter: RecordFilterArgs,
+
+    #[clap(short, long, help="File to write, - for stdout")]
+    pub output: String,
+
+    #[clap(short, long, arg_enum, help="Format of the file, default to the one of its extension (.jsonl, .parquet) or csv")]
+    pub format: Option<ExportFormat>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ImportArgs {
+    #[clap(required=true, help="Csv files to import, a directory gives the .csv files of its tree")]
+    pub paths: Vec<String>,
+
+    #[clap(short, long, help="Database to write, created if it doesn't exist")]
+    pub db: String,
+
+    #[clap(short, long, default_value=DEFAULT_TABLE, help="Table to write")]
+    pub table: String,
+
+    #[clap(long, value_name="CHAR", default_value=",", help="Delimiter of the csv files, 'tab' or '\\t' for a tab")]
+    pub delimiter: String,
+
+    #[clap(long, value_name="CHAR", default_value="\"", help="Quote character of the csv files")]
+    pub quote: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct PruneArgs {
+    #[clap(short, long, help="Database to prune, default to the yaml 'db'")]
+    pub db: Option<String>,
+
+    #[clap(short, long, help="Yaml config whose 'retention' rules are used, the options override them")]
+    pub yaml_cfg: Option<String>,
+
+    #[clap(short, long, multiple_values=true, help="Tables to prune, default to every selector record table")]
+    pub table: Vec<String>,
+
+    #[clap(long, value_name="DAYS", help="Delete the records older than this number of days")]
+    pub keep_days: Option<u64>,
+
+    #[clap(long, value_name="RUNS", help="Keep only the records seen during the last runs of each url")]
+    pub keep_runs: Option<u64>,
+
+    #[clap(long, help="Delete the records with the same url, selector and content as an older one")]
+    pub dedup: bool,
+
+    #[clap(long, help="Rebuild the database file afterwards to give back the freed space")]
+    pub vacuum: bool,
+
+    #[clap(long, help="Only count the records that would be deleted")]
+    pub dry_run: bool,
+}
+
+impl PruneArgs{
+    /// The database and the rules, cli values take precedence over the yaml ones
+    pub fn build_settings(&self) -> Result<(String, RetentionSettings)>{
+        let (yaml_db, mut settings) = match &self.yaml_cfg {
+            Some(path) => {
+                let config_file = ConfigFile::parse(&file_utils::get_file_content(path.to_string())?)?;
+                (config_file.db.clone(), config_file.retention_settings())
+            },
+            None => (None, RetentionSettings::default())
+        };
+        settings.keep_days = self.keep_days.or(settings.keep_days);
+        settings.keep_runs = self.keep_runs.or(settings.keep_runs);
+        settings.dedup |= self.dedup;
+        settings.vacuum |= self.vacuum;
+        settings.validate()?;
+        if !settings.has_rules() {
+            return Err(ScrapeError::Config(String::from("no retention rule, expected --keep-days, --keep-runs, --dedup or a yaml 'retention' section")));
+        }
+        let db = self.db.clone().or(yaml_db).ok_or_else(|| ScrapeError::Config(String::from("no database, expected --db or a yaml 'db'")))?;
+        return Ok((db, settings));
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ScrapeArgs {
     #[clap(short, long, help="Defines the Url to scrap")]
     pub url: Option<String>,
 
-    #[clap(short, long, help="Defines the CSS Selector to filter")]
+    #[clap(short, long, help="Defines the CSS Selector to filter, 'a@href' extracts an attribute, 'div::html' the inner html")]
     pub selector: Option<String>,
 
     #[clap(short, long, help="Defines the yaml file to use as config for URL and SELECTOR")]
@@ -21,37 +315,644 @@ pub struct Args {
     #[clap(short, long, help="Save result to given database")]
     pub db: Option<String>,
 
-    #[clap(short, long, default_value="selector_record", help="Save result to given table, default to 'selector_record'")]
-    pub table: String,
+    #[clap(short, long, help="Save result to given table, default to the yaml 'table' or 'selector_record'. {host} and {selector} are replaced by the ones of each pair")]
+    pub table: Option<String>,
 
     #[clap(long, help="Save results to a csv file")]
     pub to_csv: bool, 
 
+    #[clap(long, value_name="DIR", help="Directory of the csv files, default to the current one")]
+    pub csv_dir: Option<String>,
+
+    #[clap(long, value_name="TEMPLATE", help="Name of the csv files in --csv-dir, with {host}, {selector}, {table}, {run_id}, {index}, {date}, {year}, {month} and {day}, like {year}/{month}/{day}/{host}.csv")]
+    pub csv_name: Option<String>,
+
+    #[clap(long, value_name="CHAR", help="Delimiter of the csv files, default to ',', 'tab' or '\\t' for a tab")]
+    pub csv_delimiter: Option<String>,
+
+    #[clap(long, value_name="CHAR", help="Quote character of the csv files, default to '\"'")]
+    pub csv_quote: Option<String>,
+
+    #[clap(long, help="Append to the csv files of the previous runs, the header row is only written to a new file")]
+    pub csv_append: bool,
+
     #[clap(long, help="Print some stats from database records")]
     pub print_db_stats: bool,     
+
+    #[clap(long, value_name="SECS", help="Timeout to establish a connection, default to 10s")]
+    pub connect_timeout: Option<f64>,
+
+    #[clap(long, value_name="SECS", help="Timeout for a whole request including the body, default to 30s")]
+    pub read_timeout: Option<f64>,
+
+    #[clap(long, help="Number of retries after a failed request, default to 3")]
+    pub retries: Option<u32>,
+
+    #[clap(long, value_name="SECS", help="Delay before the first retry, doubled on each retry, default to 0.5s")]
+    pub backoff_base: Option<f64>,
+
+    #[clap(long, value_name="SECS", help="Maximum delay between two retries, default to 30s")]
+    pub backoff_max: Option<f64>,
+
+    #[clap(long, multiple_values=true, value_name="STATUS", help="HTTP status codes worth a retry, default to 408 429 500 502 503 504")]
+    pub retry_status: Option<Vec<u16>>,
+
+    #[clap(long, help="Maximum number of requests in flight for the whole run, default to 16")]
+    pub max_concurrency: Option<usize>,
+
+    #[clap(long, help="Maximum number of requests in flight for a single host, default to 2")]
+    pub host_max_in_flight: Option<usize>,
+
+    #[clap(long, value_name="SECS", help="Minimum delay between two requests to the same host, default to 0s")]
+    pub host_min_delay: Option<f64>,
+
+    #[clap(long, help="User-agent sent with requests and used to read robots.txt rules")]
+    pub user_agent: Option<String>,
+
+    #[clap(long, multiple_values=true, value_name="HOST", help="Hosts for which robots.txt is not checked, only for sites you own")]
+    pub ignore_robots_for: Vec<String>,
+
+    #[clap(long, help="Keep every extracted item, the yaml filters are not applied")]
+    pub no_filter: bool,
+
+    #[clap(long, help="Keep a full-text index of the selector record tables, used by the search command")]
+    pub fts: bool,
+
+    #[clap(long, help="Don't save again a selector record already in the table, only update its last_seen and seen_count")]
+    pub dedup: bool,
+
+    #[clap(long, help="Write to the database without journal nor sync, faster but a crash during a commit can corrupt it")]
+    pub fast_unsafe: bool,
+
+    #[clap(long, help="Number of records written before each commit, default to 500")]
+    pub batch_size: Option<usize>,
+
+    #[clap(long, value_name="SECS", help="Interval at which the records written since the last commit are committed, default to 2s")]
+    pub flush_interval: Option<f64>,
+
+    #[clap(long, value_name="PATH", help="Append the records to a JSON Lines file")]
+    pub jsonl: Option<String>,
+
+    #[clap(long, help="Print the records to stdout, same as --output -")]
+    pub stdout: bool,
+
+    #[clap(long, value_name="PATH", help="Write the records to a file in the --format format, - for stdout")]
+    pub output: Option<String>,
+
+    #[clap(long, arg_enum, help="Format of the records of --output and --stdout, default to jsonl")]
+    pub format: Option<OutputFormat>,
+}
+
+impl ScrapeArgs{
+    pub fn build_config(&self) -> Result<Config>{
+        let mut config: Config = match (&self.yaml_cfg, &self.url, &self.selector) {
+            (Some(v), _, _) => Config::new_from_yaml_file(v, &self.to_csv, &self.db, &self.print_db_stats)?,
+            (None, Some(url), Some(selector)) => {
+                let url_selector_vec = vec![UrlSelectorPair::new(url, selector)];
+                Config::new(&url_selector_vec, &self.to_csv, &self.db, DEFAULT_TABLE, &self.print_db_stats)
+            },
+            _ => return Err(ScrapeError::Config(String::from("either --yaml-cfg or both --url and --selector must be given")))
+        };
+        // cli values take precedence over the yaml ones
+        if let Some(table) = &self.table {
+            config.table = table.to_string();
+        }
+        config.fetch = self.fetch_overrides()?.apply_to(&config.fetch);
+        if let Some(v) = self.max_concurrency {
+            config.politeness.max_concurrency = v;
+        }
+        if let Some(v) = self.host_max_in_flight {
+            config.politeness.default_host_limits.max_in_flight = v;
+        }
+        if let Some(v) = self.host_min_delay {
+            config.politeness.default_host_limits.min_delay = parse_seconds(v, "--host-min-delay")?;
+        }
+        config.politeness.validate()?;
+        if let Some(dir) = &self.csv_dir {
+            config.csv.dir = dir.to_string();
+        }
+        if let Some(name) = &self.csv_name {
+            config.csv.name = name.to_string();
+        }
+        if let Some(delimiter) = &self.csv_delimiter {
+            config.csv.delimiter = parse_csv_char(delimiter, "delimiter")?;
+        }
+        if let Some(quote) = &self.csv_quote {
+            config.csv.quote = parse_csv_char(quote, "quote")?;
+        }
+        if self.csv_append {
+            config.csv.append = true;
+        }
+        config.csv.validate()?;
+        if let Some(v) = &self.user_agent {
+            config.robots.user_agent = v.to_string();
+        }
+        config.robots.ignore_hosts.extend(self.ignore_robots_for.iter().cloned());
+        if self.no_filter {
+            config.filter.enabled = false;
+        }
+        if self.fts {
+            config.storage.default_table_options.fts = true;
+        }
+        if self.dedup {
+            config.storage.default_table_options.dedup = true;
+        }
+        if self.fast_unsafe {
+            config.storage.profile = DbProfile::FastUnsafe;
+        }
+        if let Some(v) = self.batch_size {
+            config.writer.batch_size = v;
+        }
+        if let Some(v) = self.flush_interval {
+            config.writer.flush_interval = parse_seconds(v, "--flush-interval")?;
+        }
+        config.writer.validate()?;
+        if let Some(path) = &self.jsonl {
+            config.outputs.push(OutputSpec::Jsonl{path: path.to_string()});
+        }
+        let format = self.format.unwrap_or_else(default_stream_format);
+        match self.output.as_deref() {
+            Some("-") => config.outputs.push(OutputSpec::Stdout{format}),
+            Some(path) => config.outputs.push(OutputSpec::File{path: path.to_string(), format}),
+            None if self.format.is_some() && !self.stdout => return Err(ScrapeError::Config(String::from("--format needs --output or --stdout"))),
+            None => {}
+        }
+        if self.stdout {
+            config.outputs.push(OutputSpec::Stdout{format});
+        }
+        config.resolve_tables()?;
+        return Ok(config);
+    }
+
+    fn fetch_overrides(&self) -> Result<FetchOverrides>{
+        let seconds = |value: Option<f64>, what: &str| value.map(|v| parse_seconds(v, what)).transpose();
+        return Ok(FetchOverrides{
+            connect_timeout: seconds(self.connect_timeout, "--connect-timeout")?,
+            read_timeout: seconds(self.read_timeout, "--read-timeout")?,
+            retries: self.retries,
+            backoff_base: seconds(self.backoff_base, "--backoff-base")?,
+            backoff_max: seconds(self.backoff_max, "--backoff-max")?,
+            retry_statuses: self.retry_status.clone(),
+            headers: None
+        });
+    }
+}
+
+/// Timeouts and retry policy used to fetch a page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FetchSettings{
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub retries: u32,
+    pub backoff_base: Duration,
+    pub backoff_max: Duration,
+    pub retry_statuses: Vec<u16>,
+    pub headers: BTreeMap<String, String>
+}
+
+impl Default for FetchSettings{
+    fn default() -> FetchSettings{
+        return FetchSettings{
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff_base: Duration::from_millis(500),
+            backoff_max: Duration::from_secs(30),
+            retry_statuses: vec![408, 429, 500, 502, 503, 504],
+            headers: BTreeMap::new()
+        };
+    }
+}
+
+impl FetchSettings{
+    /// Delay before the given retry (starting at 0) : base * 2^retry, capped to backoff_max
+    pub fn backoff_delay(&self, retry: u32) -> Duration{
+        let factor = 2u32.saturating_pow(retry);
+        return self.backoff_base.saturating_mul(factor).min(self.backoff_max);
+    }
+
+    pub fn is_retryable_status(&self, status: u16) -> bool{
+        return self.retry_statuses.contains(&status);
+    }
+
+    /// The most permissive of both, for a page fetched once for pairs with their own settings :
+    /// longest timeouts and delays, most retries, every retryable status. A header set by both keeps the value of `self`.
+    pub fn merge(&self, other: &FetchSettings) -> FetchSettings{
+        let mut retry_statuses = self.retry_statuses.clone();
+        retry_statuses.extend(other.retry_statuses.iter().filter(|s| !self.retry_statuses.contains(s)));
+        let mut headers = other.headers.clone();
+        headers.extend(self.headers.clone());
+        return FetchSettings{
+            connect_timeout: self.connect_timeout.max(other.connect_timeout),
+            read_timeout: self.read_timeout.max(other.read_timeout),
+            retries: self.retries.max(other.retries),
+            backoff_base: self.backoff_base.max(other.backoff_base),
+            backoff_max: self.backoff_max.max(other.backoff_max),
+            retry_statuses,
+            headers
+        };
+    }
+}
+
+/// Partial FetchSettings, from the cli, the yaml top level `fetch` key or a single source.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FetchOverrides{
+    pub connect_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    pub retries: Option<u32>,
+    pub backoff_base: Option<Duration>,
+    pub backoff_max: Option<Duration>,
+    pub retry_statuses: Option<Vec<u16>>,
+    /// Added to the inherited headers, replacing the ones with the same name
+    pub headers: Option<BTreeMap<String, String>>
+}
+
+impl FetchOverrides{
+    pub fn apply_to(&self, settings: &FetchSettings) -> FetchSettings{
+        return FetchSettings{
+            connect_timeout: self.connect_timeout.unwrap_or(settings.connect_timeout),
+            read_timeout: self.read_timeout.unwrap_or(settings.read_timeout),
+            retries: self.retries.unwrap_or(settings.retries),
+            backoff_base: self.backoff_base.unwrap_or(settings.backoff_base),
+            backoff_max: self.backoff_max.unwrap_or(settings.backoff_max),
+            retry_statuses: self.retry_statuses.clone().unwrap_or_else(|| settings.retry_statuses.clone()),
+            headers: settings.headers.iter().chain(self.headers.iter().flatten())
+                                     .map(|(name, value)| (name.clone(), value.clone()))
+                                     .collect()
+        };
+    }
+}
+
+/// Limits applied to the requests sent to a single host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostLimits{
+    pub max_in_flight: usize,
+    pub min_delay: Duration
+}
+
+impl Default for HostLimits{
+    fn default() -> HostLimits{
+        return HostLimits{max_in_flight: 2, min_delay: Duration::ZERO};
+    }
+}
+
+/// Global and per host limits, hosts are the ones computed by `get_host_from_url`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolitenessSettings{
+    pub max_concurrency: usize,
+    pub default_host_limits: HostLimits,
+    pub hosts: HashMap<String, HostLimits>
+}
+
+impl Default for PolitenessSettings{
+    fn default() -> PolitenessSettings{
+        return PolitenessSettings{max_concurrency: 16, default_host_limits: HostLimits::default(), hosts: HashMap::new()};
+    }
+}
+
+impl PolitenessSettings{
+    pub fn limits_for(&self, host: &str) -> &HostLimits{
+        return self.hosts.get(host).unwrap_or(&self.default_host_limits);
+    }
+
+    pub fn validate(&self) -> Result<()>{
+        let zero_limit = self.max_concurrency == 0 
+                         || self.default_host_limits.max_in_flight == 0
+                         || self.hosts.values().any(|l| l.max_in_flight == 0);
+        if zero_limit {
+            return Err(ScrapeError::Config(String::from("concurrency limits must be at least 1")));
+        }
+        return Ok(());
+    }
+}
+
+/// Filters applied to the items extracted by a selector, the whitespace normalization only applies to texts
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterSettings{
+    pub enabled: bool,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    pub normalize_whitespace: bool
+}
+
+impl Default for FilterSettings{
+    fn default() -> FilterSettings{
+        return FilterSettings{enabled: true, include: Vec::new(), exclude: Vec::new(),
+                              min_length: Some(4), max_length: None, normalize_whitespace: true};
+    }
+}
+
+/// Partial FilterSettings, from the yaml top level `filters` key or a single source.
+/// Include and exclude lists replace the global ones, `Some(None)` lengths clear the inherited ones.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilterOverrides{
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub min_length: Option<Option<usize>>,
+    pub max_length: Option<Option<usize>>,
+    pub normalize_whitespace: Option<bool>
+}
+
+impl FilterOverrides{
+    pub fn apply_to(&self, settings: &FilterSettings) -> FilterSettings{
+        return FilterSettings{
+            enabled: settings.enabled,
+            include: self.include.clone().unwrap_or_else(|| settings.include.clone()),
+            exclude: self.exclude.clone().unwrap_or_else(|| settings.exclude.clone()),
+            min_length: self.min_length.unwrap_or(settings.min_length),
+            max_length: self.max_length.unwrap_or(settings.max_length),
+            normalize_whitespace: self.normalize_whitespace.unwrap_or(settings.normalize_whitespace)
+        };
+    }
+}
+
+pub const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// robots.txt compliance, enabled by default
+#[derive(Debug, Clone, PartialEq)]
+pub struct RobotsSettings{
+    pub enabled: bool,
+    pub user_agent: String,
+    pub ignore_hosts: Vec<String>
+}
+
+impl Default for RobotsSettings{
+    fn default() -> RobotsSettings{
+        return RobotsSettings{enabled: true, user_agent: DEFAULT_USER_AGENT.to_string(), ignore_hosts: Vec::new()};
+    }
+}
+
+/// How the records of a table are saved
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TableOptions{
+    /// Full-text index of the content column, only for the selector record tables
+    pub fts: bool,
+    /// A selector record already saved with the same url, selector and content is only marked as seen again
+    pub dedup: bool
+}
+
+/// SQLite settings of the scrape connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DbProfile{
+    /// WAL journal : a crash can't corrupt the database and readers can open it during a scrape
+    #[default]
+    Durable,
+    /// No journal and an exclusive lock, faster but a crash during a commit can corrupt the database
+    FastUnsafe
+}
+
+impl DbProfile{
+    fn pragmas(&self) -> Vec<(&'static str, &'static str)>{
+        return match self {
+            DbProfile::Durable => vec![("journal_mode", "WAL"), ("synchronous", "NORMAL"), ("busy_timeout", "5000"), ("temp_store", "MEMORY")],
+            DbProfile::FastUnsafe => vec![("journal_mode", "OFF"), ("synchronous", "OFF"), ("cache_size", "1000000"),
+                                          ("locking_mode", "EXCLUSIVE"), ("temp_store", "MEMORY")],
+        };
+    }
 }
 
-impl Args{
-    pub fn build_config(&self) -> Config{
-        let config: Config = match &self.yaml_cfg {
-            Some(v) => Config::new_from_yaml_file(v, &self.to_csv, &self.db, &self.print_db_stats).unwrap(),
-            _ => {
-                let mut url_selector_vec = Vec::new();
-                url_selector_vec.push(
-                    UrlSelectorPair{url: self.url.as_ref().unwrap().to_string(),
-                                    selector: self.selector.as_ref().unwrap().to_string()}
-                );
-                Config::new(&url_selector_vec, &self.to_csv, &self.db, &self.table, &self.print_db_stats)
+/// Options of the tables, the default ones apply to the tables without their own entry
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StorageSettings{
+    pub default_table_options: TableOptions,
+    pub tables: HashMap<String, TableOptions>,
+    pub profile: DbProfile,
+    /// Replace the pragmas of the profile with the same name, the other ones are applied after them
+    pub pragmas: BTreeMap<String, String>
+}
+
+impl StorageSettings{
+    pub fn options_for(&self, table: &str) -> &TableOptions{
+        return self.tables.get(table).unwrap_or(&self.default_table_options);
+    }
+
+    /// Pragmas of the profile with the overrides, in the order they are applied
+    pub fn pragmas(&self) -> Vec<(String, String)>{
+        let mut pragmas: Vec<(String, String)> = self.profile.pragmas().into_iter()
+            .map(|(name, value)| (name.to_string(), self.pragmas.get(name).cloned().unwrap_or_else(|| value.to_string())))
+            .collect();
+        for (name, value) in &self.pragmas {
+            if !pragmas.iter().any(|(n, _)| n == name) {
+                pragmas.push((name.clone(), value.clone()));
             }
+        }
+        return pragmas;
+    }
+}
+
+/// How the records reach the sinks : a writer thread receives the pages as their requests complete
+/// and commits their records in batches, so a scrape keeps what it already got if a late request hangs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WriterSettings{
+    /// Records written before a commit
+    pub batch_size: usize,
+    /// The records written since the last commit are also committed at this interval
+    pub flush_interval: Duration,
+    /// Pages waiting for the writer, the next requests wait once it's full so that memory stays bounded
+    pub queue_size: usize
+}
+
+impl Default for WriterSettings{
+    fn default() -> WriterSettings{
+        return WriterSettings{batch_size: 500, flush_interval: Duration::from_secs(2), queue_size: 32};
+    }
+}
+
+impl WriterSettings{
+    pub fn validate(&self) -> Result<()>{
+        if self.batch_size == 0 || self.queue_size == 0 || self.flush_interval.is_zero() {
+            return Err(ScrapeError::Config(String::from("the writer batch size, queue size and flush interval must be more than 0")));
+        }
+        return Ok(());
+    }
+}
+
+/// Records removed by `prune`, and at the end of a scrape with `after_scrape`.
+/// The rules apply to the selector record tables in this order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RetentionSettings{
+    /// Records older than this number of days are deleted
+    pub keep_days: Option<u64>,
+    /// Only the records seen during the last runs of each url are kept
+    pub keep_runs: Option<u64>,
+    /// Records with the same url, selector and content as an older one are deleted, the oldest one gets their seen_count and last_seen
+    pub dedup: bool,
+    pub vacuum: bool,
+    pub after_scrape: bool
+}
+
+impl RetentionSettings{
+    pub fn has_rules(&self) -> bool{
+        return self.keep_days.is_some() || self.keep_runs.is_some() || self.dedup;
+    }
+
+    pub fn validate(&self) -> Result<()>{
+        if self.keep_days == Some(0) || self.keep_runs == Some(0) {
+            return Err(ScrapeError::Config(String::from("keep_days and keep_runs must be at least 1")));
+        }
+        return Ok(());
+    }
+}
+
+pub const DEFAULT_CSV_NAME: &str = "records_{host}_{run_id}_{index}.csv";
+
+/// How the csv fields are quoted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvQuoteStyle{
+    /// Only the fields with a delimiter, a quote or a line break
+    #[default]
+    Necessary,
+    Always,
+    NonNumeric,
+    Never
+}
+
+impl CsvQuoteStyle{
+    pub fn to_csv(self) -> csv::QuoteStyle{
+        return match self {
+            CsvQuoteStyle::Necessary => csv::QuoteStyle::Necessary,
+            CsvQuoteStyle::Always => csv::QuoteStyle::Always,
+            CsvQuoteStyle::NonNumeric => csv::QuoteStyle::NonNumeric,
+            CsvQuoteStyle::Never => csv::QuoteStyle::Never,
         };
-        return config;
     }
 }
 
+/// Files of the `to_csv` output. The name is a template relative to `dir`, its '/' make subdirectories.
+/// A file is appended to when several pairs of a run share it, and from a run to the next with `append`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvSettings{
+    pub dir: String,
+    pub name: String,
+    pub delimiter: u8,
+    pub quote: u8,
+    pub quote_style: CsvQuoteStyle,
+    pub append: bool
+}
+
+impl Default for CsvSettings{
+    fn default() -> CsvSettings{
+        return CsvSettings{dir: String::from("."), name: DEFAULT_CSV_NAME.to_string(), delimiter: b',', quote: b'"',
+                           quote_style: CsvQuoteStyle::default(), append: false};
+    }
+}
+
+impl CsvSettings{
+    /// Fails on a bad name template before any request is sent
+    pub fn validate(&self) -> Result<()>{
+        self.file_name("www.cnews.fr", "h3", "selector_record", 1, 0, 0)?;
+        if self.delimiter == self.quote {
+            return Err(ScrapeError::Config(String::from("the csv delimiter and quote must be different")));
+        }
+        return Ok(());
+    }
+
+    /// Path of a file relative to `dir`, the date is the UTC one of `timestamp`
+    pub fn file_name(&self, host: &str, selector: &str, table: &str, run_id: i64, index: usize, timestamp: u64) -> Result<String>{
+        let invalid = |reason: &str| ScrapeError::Config(format!("invalid csv name '{}': {}", self.name, reason));
+        let (year, month, day) = civil_from_timestamp(timestamp);
+        let values = [("host", host.to_string()), ("selector", table_name_part(selector)), ("table", table.to_string()),
+                      ("run_id", run_id.to_string()), ("index", index.to_string()),
+                      ("date", format!("{:04}-{:02}-{:02}", year, month, day)), ("year", format!("{:04}", year)),
+                      ("month", format!("{:02}", month)), ("day", format!("{:02}", day))];
+        let mut name = self.name.clone();
+        for (placeholder, value) in &values {
+            name = name.replace(&format!("{{{}}}", placeholder), value);
+        }
+        if name.contains('{') || name.contains('}') {
+            return Err(invalid("unknown placeholder, expected {host}, {selector}, {table}, {run_id}, {index}, {date}, {year}, {month} or {day}"));
+        }
+        if name.starts_with('/') || name.ends_with('/') || name.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(invalid("expected a relative file path without '..'"));
+        }
+        return Ok(name);
+    }
+}
+
+/// A duration given in seconds, fractions allowed, negative, NaN or too large values are rejected
+pub fn parse_seconds(secs: f64, what: &str) -> Result<Duration>{
+    return Duration::try_from_secs_f64(secs)
+        .map_err(|_| ScrapeError::Config(format!("invalid {} {}, expected a positive number of seconds", what, secs)));
+}
+
+/// A single ascii character like ";", or "tab" and "\t" for a tab
+pub fn parse_csv_char(value: &str, what: &str) -> Result<u8>{
+    return match value {
+        "tab" | "\\t" | "\t" => Ok(b'\t'),
+        v if v.len() == 1 && v.is_ascii() && !v.as_bytes()[0].is_ascii_alphanumeric() && v != "\n" && v != "\r" => Ok(v.as_bytes()[0]),
+        _ => Err(ScrapeError::Config(format!("invalid csv {} '{}', expected a single punctuation character or 'tab'", what, value)))
+    };
+}
+
+/// An output of the scraped records, each one is written by a `RecordSink`
+/// ```yaml
+/// outputs:
+///   - {type: sqlite, path: records.db}
+///   - {type: csv}                       # a file per pair in the current directory
+///   - {type: jsonl, path: records.jsonl}   # same as {type: file, path: records.jsonl, format: jsonl}
+///   - {type: file, path: records.csv, format: csv}
+///   - {type: stdout, format: table}       # format of file and stdout : jsonl (default), json, csv or table
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputSpec{
+    Sqlite{path: String},
+    Csv,
+    Jsonl{path: String},
+    File{path: String, #[serde(default = "default_stream_format")] format: OutputFormat},
+    Stdout{#[serde(default = "default_stream_format")] format: OutputFormat}
+}
+
+fn default_stream_format() -> OutputFormat{
+    return OutputFormat::Jsonl;
+}
+
+/// Replaces `{host}` and `{selector}` in a table name, like `news_{host}`, and checks the result.
+/// The replaced parts only keep the letters and digits, with '_' between them.
+pub fn expand_table_name(template: &str, url: &str, selector: &str) -> Result<String>{
+    let mut table = template.to_string();
+    if table.contains("{host}") {
+        table = table.replace("{host}", &table_name_part(&get_host_from_url(url)?));
+    }
+    if table.contains("{selector}") {
+        table = table.replace("{selector}", &table_name_part(selector));
+    }
+    if table.contains('{') || table.contains('}') {
+        return Err(ScrapeError::Config(format!("unknown placeholder in table name '{}', expected {{host}} or {{selector}}", template)));
+    }
+    check_table_name(&table)?;
+    return Ok(table);
+}
+
+fn table_name_part(value: &str) -> String{
+    return value.split(|c: char| !c.is_alphanumeric()).filter(|part| !part.is_empty()).collect::<Vec<&str>>().join("_");
+}
+
+/// An url with the CSS selector to extract. With `fields`, the selector matches items
+/// and each field selector is applied inside them to build one structured record per item.
 #[derive(Debug, Clone)]
 pub struct UrlSelectorPair{
     pub url: String,
-    pub selector: String
+    pub selector: String,
+    pub fetch: FetchOverrides,
+    pub filter: FilterOverrides,
+    pub fields: Vec<FieldSpec>,
+    pub table: Option<String>
+}
+
+impl UrlSelectorPair{
+    pub fn new(url: &str, selector: &str) -> UrlSelectorPair{
+        return UrlSelectorPair{url: url.to_string(), selector: selector.to_string(), fetch: FetchOverrides::default(),
+                               filter: FilterOverrides::default(), fields: Vec::new(), table: None};
+    }
+
+    pub fn is_structured(&self) -> bool{
+        return !self.fields.is_empty();
+    }
 }
 
 #[derive(Debug)]
@@ -61,52 +962,143 @@ pub struct Config{
     pub db_path: Option<String>,
     pub table: String,
     pub print_db_stats: bool,
+    pub fetch: FetchSettings,
+    pub politeness: PolitenessSettings,
+    pub robots: RobotsSettings,
+    pub filter: FilterSettings,
+    pub storage: StorageSettings,
+    pub csv: CsvSettings,
+    pub retention: RetentionSettings,
+    pub writer: WriterSettings,
+    /// Outputs other than the `db` and `to_csv` ones
+    pub outputs: Vec<OutputSpec>,
+    /// Problems found in the yaml file that don't prevent running, like unknown keys
+    pub warnings: Vec<String>,
     pub env_arg1: bool
 }
 
 impl Config{
-    pub fn new(url_selectors: &Vec<UrlSelectorPair>, save_to_csv:&bool, db_path: &Option<String>, table: &String, print_db_stats:&bool) -> Config{   
+    pub fn new(url_selectors: &[UrlSelectorPair], save_to_csv:&bool, db_path: &Option<String>, table: &str, print_db_stats:&bool) -> Config{   
         let env_arg1 = env::var("WEB_SCRAP_CLI_ARG1").is_err();        
-        return Config {url_selectors: url_selectors.clone(), 
-            save_to_csv: save_to_csv.clone(),
+        return Config {url_selectors: url_selectors.to_vec(), 
+            save_to_csv: *save_to_csv,
             db_path: db_path.clone(),
-            table: table.clone(),
-            print_db_stats: print_db_stats.clone(),
+            table: table.to_string(),
+            print_db_stats: *print_db_stats,
+            fetch: FetchSettings::default(),
+            politeness: PolitenessSettings::default(),
+            robots: RobotsSettings::default(),
+            filter: FilterSettings::default(),
+            storage: StorageSettings::default(),
+            csv: CsvSettings::default(),
+            retention: RetentionSettings::default(),
+            writer: WriterSettings::default(),
+            outputs: Vec::new(),
+            warnings: Vec::new(),
             env_arg1};
     }
 
-    pub fn new_from_yaml_file(yaml_cfg: &String, save_to_csv:&bool, db_path: &Option<String>, print_db_stats: &bool) -> Result<Config, Box<dyn Error>>{
-        println!("Current dir : {:?}", std::env::current_dir());
-        println!("Current exe: {:?}", std::env::current_exe());
+    pub fn new_from_yaml_file(yaml_cfg: &str, save_to_csv:&bool, db_path: &Option<String>, print_db_stats: &bool) -> Result<Config>{
+        debug!("Current dir : {:?}", std::env::current_dir());
+        debug!("Current exe: {:?}", std::env::current_exe());
 
-        let content = file_utils::get_file_content((&yaml_cfg).to_string()).expect("Can't get yaml file content !");
+        let content = file_utils::get_file_content(yaml_cfg.to_string())?;
         return Config::new_from_yaml_string(&content, save_to_csv, db_path, print_db_stats);
     }
 
-    pub fn new_from_yaml_string(yaml_content: &str, save_to_csv:&bool, db_path: &Option<String>, print_db_stats: &bool)-> Result<Config, Box<dyn Error>>{
-        let yaml_vec = YamlLoader::load_from_str(yaml_content)?;   
-        let yaml = &yaml_vec[0] ;
+    /// Values given to the cli take precedence over the ones of the file
+    pub fn new_from_yaml_string(yaml_content: &str, save_to_csv:&bool, db_path: &Option<String>, print_db_stats: &bool)-> Result<Config>{
+        let config_file = ConfigFile::parse(yaml_content)?;
+        let url_selectors = config_file.url_selectors()?;
+        let db_path = db_path.clone().or_else(|| config_file.db.clone());
+        let table = config_file.table.as_deref().unwrap_or(DEFAULT_TABLE);
+        let mut config = Config::new(&url_selectors, &(*save_to_csv || config_file.to_csv), &db_path, table, print_db_stats);
+        config.fetch = config_file.fetch_overrides().apply_to(&config.fetch);
+        config.politeness = config_file.politeness_settings();
+        config.robots = config_file.robots_settings();
+        config.storage = config_file.storage_settings();
+        config.csv = config_file.csv_settings();
+        config.retention = config_file.retention_settings();
+        config.retention.validate()?;
+        config.writer = config_file.writer_settings();
+        config.outputs = config_file.outputs.clone();
+        config.filter = config_file.filter_overrides().apply_to(&config.filter);
+        config.warnings = config_file.warnings;
+        return Ok(config);
+    }
+
+    /// Every output of the run once : the database, the csv files, then the other ones in their order
+    pub fn output_specs(&self) -> Vec<OutputSpec>{
+        let mut specs = Vec::new();
+        if let Some(path) = &self.db_path {
+            specs.push(OutputSpec::Sqlite{path: path.clone()});
+        }
+        if self.save_to_csv {
+            specs.push(OutputSpec::Csv);
+        }
+        for spec in &self.outputs {
+            if !specs.contains(spec) {
+                specs.push(spec.clone());
+            }
+        }
+        return specs;
+    }
+
+    /// Sets the table of every pair, its own one or the default one with the placeholders replaced
+    pub fn resolve_tables(&mut self) -> Result<()>{
+        for url_selector in self.url_selectors.iter_mut() {
+            let template = url_selector.table.as_deref().unwrap_or(&self.table);
+            url_selector.table = Some(expand_table_name(template, &url_selector.url, &url_selector.selector)?);
+        }
+        return Ok(());
+    }
 
-        let url_selector_tuples = yaml["url_selector_tuples"].as_vec().expect("Couldn't find 'url_selevtor_tuples' list in the yaml !");
-        let mut url_selectors: Vec<UrlSelectorPair> = Vec::new();
-        let mut yaml_item;
-        for elem in url_selector_tuples {
-            yaml_item = elem.as_vec().unwrap();
-            url_selectors.push(
-                UrlSelectorPair{url: yaml_item[0].as_str().unwrap().to_string(),
-                                selector: yaml_item[1].as_str().unwrap().to_string()})
+    /// Identifies what a run scrapes and how it's extracted and filtered, saved with the run.
+    /// The politeness, robots and storage settings are left out, like the output paths.
+    pub fn config_hash(&self) -> String{
+        let described = format!("{:?}\n{}\n{:?}\n{:?}", self.url_selectors, self.table, self.fetch, self.filter);
+        return format!("{:x}", Sha256::digest(described.as_bytes()));
+    }
+
+    /// Pairs sharing the same url, in the config order, so that each page is fetched only once
+    pub fn url_selector_groups(&self) -> Vec<Vec<UrlSelectorPair>>{
+        let mut groups: Vec<Vec<UrlSelectorPair>> = Vec::new();
+        let mut group_index: HashMap<&str, usize> = HashMap::new();
+        for url_selector in &self.url_selectors {
+            match group_index.get(url_selector.url.as_str()) {
+                Some(i) => groups[*i].push(url_selector.clone()),
+                None => {
+                    group_index.insert(&url_selector.url, groups.len());
+                    groups.push(vec![url_selector.clone()]);
+                }
+            }
         }
-        return Ok(Config::new(&url_selectors, save_to_csv, db_path, &String::from("selector_record"), print_db_stats));
+        return groups;
+    }
+
+    pub fn fetch_settings_for(&self, url_selector: &UrlSelectorPair) -> FetchSettings{
+        return url_selector.fetch.apply_to(&self.fetch);
+    }
+
+    /// Settings of a page shared by the pairs of a `url_selector_groups` group, merged from the ones of each pair
+    pub fn fetch_settings_for_group(&self, url_selectors: &[UrlSelectorPair]) -> FetchSettings{
+        return url_selectors.iter().skip(1)
+                            .fold(self.fetch_settings_for(&url_selectors[0]), |merged, p| merged.merge(&self.fetch_settings_for(p)));
+    }
+
+    pub fn filter_settings_for(&self, url_selector: &UrlSelectorPair) -> FilterSettings{
+        return url_selector.filter.apply_to(&self.filter);
     }
 
     pub fn print_info(&self){
-        println!("{:?}", &self);
+        debug!("{:?}", &self);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::scrap_utils::FieldType;
 
     #[test]
     fn test_new_from_yaml_ok() {
@@ -114,7 +1106,7 @@ mod tests {
         url_selector_tuples: 
             - [https://www.google.fr, div]
         "#;
-        let config = Config::new_from_yaml_string(&fake_yaml_content, &false, &None, &false);
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false);
         assert!(config.is_ok(), "{}", format!("config = {:#?}", config));  
 
         let config_ok = &config.unwrap();
@@ -125,22 +1117,384 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
-    fn test_new_from_yaml_wrong_list_panic() {
+    fn test_new_from_yaml_fetch_settings() {
+        let fake_yaml_content: &str = r#"
+        fetch:
+            read_timeout: 12
+            retries: 1
+            retry_statuses: [503]
+        url_selector_tuples: 
+            - [https://www.google.fr, div]
+            - [https://www.cnews.fr/, h3, {retries: 5, backoff_base: 0.25}]
+        "#;
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false).unwrap();
+        assert_eq!(config.fetch.read_timeout, Duration::from_secs(12));
+        assert_eq!(config.fetch.retry_statuses, vec![503]);
+
+        let default_pair = config.fetch_settings_for(&config.url_selectors[0]);
+        assert_eq!(default_pair.retries, 1);
+        let tuned_pair = config.fetch_settings_for(&config.url_selectors[1]);
+        assert_eq!(tuned_pair.retries, 5);
+        assert_eq!(tuned_pair.backoff_base, Duration::from_millis(250));
+        assert_eq!(tuned_pair.read_timeout, Duration::from_secs(12));
+    }
+
+    #[test]
+    fn test_fetch_settings_for_group() {
+        let fake_yaml_content: &str = r#"
+        fetch:
+            retry_statuses: [503]
+        sources:
+            - url: https://www.cnews.fr/
+              selector: h3
+              headers: {Accept-Language: fr}
+              fetch: {read_timeout: 5, retries: 1}
+            - url: https://www.cnews.fr/
+              selector: a@href
+              headers: {Accept-Language: en, X-Test: "1"}
+              fetch: {read_timeout: 60, retry_statuses: [429]}
+        "#;
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false).unwrap();
+        let groups = config.url_selector_groups();
+        assert_eq!(groups.len(), 1);
+        let settings = config.fetch_settings_for_group(&groups[0]);
+        assert_eq!(settings.read_timeout, Duration::from_secs(60));
+        assert_eq!(settings.retries, 3);
+        assert_eq!(settings.retry_statuses, vec![503, 429]);
+        assert_eq!(settings.headers.get("Accept-Language").map(String::as_str), Some("fr"));
+        assert_eq!(settings.headers.get("X-Test").map(String::as_str), Some("1"));
+    }
+
+    #[test]
+    fn test_new_from_yaml_politeness() {
+        let fake_yaml_content: &str = r#"
+        politeness:
+            max_concurrency: 4
+            min_delay: 1
+            hosts:
+                www.cnews.fr: {max_in_flight: 1}
+        url_selector_tuples: 
+            - [https://www.google.fr, div]
+        "#;
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false).unwrap();
+        assert_eq!(config.politeness.max_concurrency, 4);
+        assert_eq!(config.politeness.limits_for("www.google.fr"), &HostLimits{max_in_flight: 2, min_delay: Duration::from_secs(1)});
+        assert_eq!(config.politeness.limits_for("www.cnews.fr"), &HostLimits{max_in_flight: 1, min_delay: Duration::from_secs(1)});
+    }
+
+    #[test]
+    fn test_new_from_yaml_robots() {
+        let fake_yaml_content: &str = r#"
+        robots:
+            user_agent: my_bot/1.0
+            ignore_hosts: [www.my-site.fr]
+        url_selector_tuples: 
+            - [https://www.google.fr, div]
+        "#;
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false).unwrap();
+        assert!(config.robots.enabled);
+        assert_eq!(config.robots.user_agent, "my_bot/1.0");
+        assert_eq!(config.robots.ignore_hosts, vec![String::from("www.my-site.fr")]);
+    }
+
+    #[test]
+    fn test_new_from_yaml_storage() {
+        let fake_yaml_content: &str = r#"
+        storage:
+            fts: true
+            tables:
+                cnews_article: {fts: false, dedup: true}
+            pragmas: {synchronous: FULL, cache_size: -64000}
+        url_selector_tuples: 
+            - [https://www.google.fr, div]
+        "#;
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false).unwrap();
+        assert!(config.storage.options_for("selector_record").fts);
+        assert_eq!(config.storage.options_for("cnews_article"), &TableOptions{fts: false, dedup: true});
+        assert_eq!(config.storage.profile, DbProfile::Durable);
+        let pragmas = config.storage.pragmas();
+        assert_eq!(pragmas[0], (String::from("journal_mode"), String::from("WAL")));
+        assert_eq!(pragmas[1], (String::from("synchronous"), String::from("FULL")));
+        assert_eq!(pragmas.last().unwrap(), &(String::from("cache_size"), String::from("-64000")));
+
+        let fake_yaml_content: &str = r#"
+        storage:
+            profile: fast_unsafe
+            pragmas: {"journal_mode = OFF; DROP TABLE runs": x}
+        url_selector_tuples: 
+            - [https://www.google.fr, div]
+        "#;
+        let error = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false).unwrap_err().to_string();
+        assert!(error.contains("invalid pragma"), "{}", error);
+    }
+
+    #[test]
+    fn test_table_name_templates() {
+        assert_eq!(expand_table_name("news_{host}", "https://www.cnews.fr/sport", "h3").unwrap(), "news_www_cnews_fr");
+        assert_eq!(expand_table_name("{host}-{selector}", "https://www.cnews.fr/", "div.title > a@href").unwrap(), "www_cnews_fr-div_title_a_href");
+        assert!(expand_table_name("news_{site}", "https://www.cnews.fr/", "h3").is_err());
+        assert!(expand_table_name("x; DROP TABLE runs", "https://www.cnews.fr/", "h3").is_err());
+
+        let fake_yaml_content: &str = r#"
+        table: "{host}"
+        sources:
+            - url: https://www.cnews.fr/
+              selector: h3
+            - url: https://www.google.fr/
+              selector: h3
+              table: google news
+        "#;
+        let yaml_path = std::env::temp_dir().join("web_scrap_cli_test_table_templates.yaml");
+        std::fs::write(&yaml_path, fake_yaml_content).unwrap();
+        let args = Args::try_parse_from(["web_scrap_cli", "-y", yaml_path.to_str().unwrap()]).unwrap();
+        let config = args.scrape.build_config().unwrap();
+        let tables: Vec<Option<String>> = config.url_selectors.iter().map(|p| p.table.clone()).collect();
+        assert_eq!(tables, vec![Some(String::from("www_cnews_fr")), Some(String::from("google news"))]);
+    }
+
+    #[test]
+    fn test_csv_settings() {
+        let settings = CsvSettings{name: String::from("{year}/{month}/{day}/{host}_{selector}.csv"), ..CsvSettings::default()};
+        assert_eq!(settings.file_name("www.cnews.fr", "div.title > a", "selector_record", 3, 1, 1709251200).unwrap(),
+                   "2024/03/01/www.cnews.fr_div_title_a.csv");
+        assert_eq!(CsvSettings::default().file_name("www.cnews.fr", "h3", "selector_record", 3, 1, 0).unwrap(), "records_www.cnews.fr_3_1.csv");
+        for name in ["{hour}.csv", "/tmp/{host}.csv", "../{host}.csv", "{date}/"] {
+            assert!(CsvSettings{name: name.to_string(), ..CsvSettings::default()}.validate().is_err(), "{}", name);
+        }
+        assert_eq!(parse_csv_char("tab", "delimiter").unwrap(), b'\t');
+        assert_eq!(parse_csv_char(";", "delimiter").unwrap(), b';');
+        assert!(parse_csv_char("ab", "delimiter").is_err());
+
+        let fake_yaml_content: &str = r#"
+        csv:
+            dir: exports
+            name: "{date}/{host}.csv"
+            delimiter: ";"
+            quote_style: always
+            append: true
+        url_selector_tuples:
+            - [https://www.cnews.fr/, h3]
+        "#;
+        let config = Config::new_from_yaml_string(fake_yaml_content, &true, &None, &false).unwrap();
+        assert_eq!(config.csv, CsvSettings{dir: String::from("exports"), name: String::from("{date}/{host}.csv"), delimiter: b';',
+                                           quote_style: CsvQuoteStyle::Always, append: true, ..CsvSettings::default()});
+        assert!(Config::new_from_yaml_string(&fake_yaml_content.replace("\";\"", "\"semicolon\""), &true, &None, &false).is_err());
+
+        let args = Args::try_parse_from(["web_scrap_cli", "--url", "https://www.cnews.fr/", "--selector", "h3", "--csv-delimiter", ";", "--csv-quote", "'"]).unwrap();
+        let config = args.scrape.build_config().unwrap();
+        assert_eq!((config.csv.delimiter, config.csv.quote), (b';', b'\''));
+        let args = Args::try_parse_from(["web_scrap_cli", "--url", "https://www.cnews.fr/", "--selector", "h3", "--csv-quote", ","]).unwrap();
+        assert!(matches!(args.scrape.build_config(), Err(ScrapeError::Config(_))));
+    }
+
+    #[test]
+    fn test_url_selector_groups() {
+        let fake_yaml_content: &str = r#"
+        url_selector_tuples: 
+            - [https://www.cnews.fr/, h3]
+            - [https://www.google.fr, div]
+            - [https://www.cnews.fr/, a]
+        "#;
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false).unwrap();
+        let groups = config.url_selector_groups();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].iter().map(|p| p.selector.as_str()).collect::<Vec<&str>>(), vec!["h3", "a"]);
+        assert_eq!(groups[1][0].url, "https://www.google.fr");
+    }
+
+    #[test]
+    fn test_new_from_yaml_structured_items() {
+        let fake_yaml_content: &str = r#"
+        structured_items:
+            - url: https://www.cnews.fr/
+              item: article
+              table: cnews_article
+              fields:
+                  title: h2
+                  views: {selector: span.views, type: integer}
+        "#;
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false).unwrap();
+        let url_selector = &config.url_selectors[0];
+        assert!(url_selector.is_structured());
+        assert_eq!(url_selector.selector, "article");
+        assert_eq!(url_selector.table, Some(String::from("cnews_article")));
+        assert_eq!(url_selector.fields[1], FieldSpec::new("views", "span.views", FieldType::Integer).unwrap());
+
+        let missing_table: &str = r#"
+        structured_items:
+            - {url: https://www.cnews.fr/, item: article, fields: {title: h2}}
+        "#;
+        assert!(Config::new_from_yaml_string(missing_table, &false, &None, &false).is_err());
+
+        let reserved_field: &str = r#"
+        structured_items:
+            - {url: https://www.cnews.fr/, item: article, table: article, fields: {Host: span.host}}
+        "#;
+        let error = Config::new_from_yaml_string(reserved_field, &false, &None, &false).unwrap_err().to_string();
+        assert!(error.contains("invalid field name 'Host'"), "{}", error);
+    }
+
+    #[test]
+    fn test_new_from_yaml_filters() {
+        let fake_yaml_content: &str = r#"
+        filters:
+            exclude: ['^Publicité']
+            max_length: 200
+        url_selector_tuples: 
+            - [https://www.google.fr, div]
+            - [https://www.cnews.fr/, h3, {min_length: 2, include: ['^\p{Lu}'], normalize_whitespace: false}]
+        "#;
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false).unwrap();
+        let default_pair = config.filter_settings_for(&config.url_selectors[0]);
+        assert_eq!(default_pair.min_length, Some(4));
+        assert_eq!(default_pair.max_length, Some(200));
+        let tuned_pair = config.filter_settings_for(&config.url_selectors[1]);
+        assert_eq!(tuned_pair.min_length, Some(2));
+        assert_eq!(tuned_pair.include, vec![String::from(r"^\p{Lu}")]);
+        assert_eq!(tuned_pair.exclude, vec![String::from("^Publicité")]);
+        assert!(!tuned_pair.normalize_whitespace);
+
+        let cleared_lengths: &str = r#"
+        filters: {max_length: 200}
+        sources:
+            - {url: https://www.cnews.fr/, selector: h3, filters: {min_length: none, max_length: none}}
+        "#;
+        let config = Config::new_from_yaml_string(cleared_lengths, &false, &None, &false).unwrap();
+        let cleared_pair = config.filter_settings_for(&config.url_selectors[0]);
+        assert_eq!((cleared_pair.min_length, cleared_pair.max_length), (None, None));
+
+        let structured_filters: &str = r#"
+        sources:
+            - {url: https://www.cnews.fr/, selector: article, table: article, fields: {title: h2}, filters: {min_length: 10}}
+        "#;
+        let error = Config::new_from_yaml_string(structured_filters, &false, &None, &false).unwrap_err().to_string();
+        assert!(error.contains("filters don't apply to structured items"), "{}", error);
+
+        let invalid_regex: &str = r#"
+        filters: {include: ['(']}
+        url_selector_tuples: 
+            - [https://www.google.fr, div]
+        "#;
+        assert!(Config::new_from_yaml_string(invalid_regex, &false, &None, &false).is_err());
+    }
+
+    #[test]
+    fn test_new_from_yaml_retention() {
+        let fake_yaml_content: &str = r#"
+        retention: {keep_days: 30, dedup: true, after_scrape: true}
+        url_selector_tuples: 
+            - [https://www.google.fr, div]
+        "#;
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false).unwrap();
+        assert_eq!(config.retention, RetentionSettings{keep_days: Some(30), keep_runs: None, dedup: true, vacuum: false, after_scrape: true});
+
+        let args = Args::try_parse_from(["web_scrap_cli", "prune", "--db", "records.db", "--keep-runs", "0"]).unwrap();
+        match args.command {
+            Some(Command::Prune(prune_args)) => {
+                let error = prune_args.build_settings().unwrap_err().to_string();
+                assert!(error.contains("at least 1"), "{}", error);
+            },
+            other => panic!("unexpected command {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_new_from_yaml_writer() {
+        let fake_yaml_content: &str = r#"
+        writer: {batch_size: 100, flush_interval: 0.5}
+        url_selector_tuples: 
+            - [https://www.google.fr, div]
+        "#;
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false).unwrap();
+        assert_eq!(config.writer, WriterSettings{batch_size: 100, flush_interval: Duration::from_millis(500), queue_size: 32});
+
+        let fake_yaml_content: &str = r#"
+        writer: {queue_size: 0}
+        url_selector_tuples: 
+            - [https://www.google.fr, div]
+        "#;
+        assert!(Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false).is_err());
+    }
+
+    #[test]
+    fn test_args_invalid_seconds() {
+        for value in ["-1", "NaN", "1e300"] {
+            let arg = format!("--read-timeout={}", value);
+            let args = Args::try_parse_from(["web_scrap_cli", "--url", "https://www.cnews.fr/", "--selector", "h3", &arg]).unwrap();
+            let error = args.scrape.build_config().unwrap_err().to_string();
+            assert!(error.contains("--read-timeout"), "{}", error);
+        }
+        for arg in ["--host-min-delay=-0.5", "--flush-interval=-1", "--flush-interval=NaN", "--flush-interval=0"] {
+            let args = Args::try_parse_from(["web_scrap_cli", "--url", "https://www.cnews.fr/", "--selector", "h3", arg]).unwrap();
+            assert!(matches!(args.scrape.build_config(), Err(ScrapeError::Config(_))), "{}", arg);
+        }
+    }
+
+    #[test]
+    fn test_args_subcommands() {
+        let args = Args::try_parse_from(["web_scrap_cli", "--url", "https://www.cnews.fr/", "--selector", "h3"]).unwrap();
+        assert!(args.command.is_none());
+        assert_eq!(args.scrape.selector, Some(String::from("h3")));
+
+        let args = Args::try_parse_from(["web_scrap_cli", "query", "--db", "records.db", "--limit", "5"]).unwrap();
+        match args.command {
+            Some(Command::Query(query_args)) => {
+                assert_eq!(query_args.table, DEFAULT_TABLE);
+                assert_eq!(query_args.limit, Some(5));
+            },
+            other => panic!("unexpected command {:?}", other)
+        }
+        assert!(Args::try_parse_from(["web_scrap_cli", "stats"]).is_err());
+        let args = Args::try_parse_from(["web_scrap_cli", "db", "migrate", "--db", "records.db"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Db(DbCommand::Migrate(_)))));
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let settings = FetchSettings{backoff_base: Duration::from_secs(1), backoff_max: Duration::from_secs(5), ..FetchSettings::default()};
+        assert_eq!(settings.backoff_delay(0), Duration::from_secs(1));
+        assert_eq!(settings.backoff_delay(2), Duration::from_secs(4));
+        assert_eq!(settings.backoff_delay(3), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_new_from_yaml_wrong_list_error() {
         let fake_yaml_content: &str = r#"
         wrong_list_name: 
             - [https://www.google.fr, div]
         "#;
-        let _result = Config::new_from_yaml_string(&fake_yaml_content, &false, &None, &false);
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false);
+        let error = config.unwrap_err().to_string();
+        assert!(error.contains("no url to scrap"), "{}", error);
     }
+
     #[test]
-    #[should_panic]
-    fn test_new_from_yaml_missing_item_panic() {
+    fn test_new_from_yaml_missing_item_error() {
         let fake_yaml_content: &str = r#"
         url_selector_tuples: 
+            - [https://www.google.fr, div]
             - [https://www.google.fr]
         "#;
-        let _result = Config::new_from_yaml_string(&fake_yaml_content, &false, &None, &false);
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false);
+        let error = config.unwrap_err().to_string();
+        assert!(error.contains("url_selector_tuples[1]") && error.contains("line 4"), "{}", error);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_new_from_yaml_sources_and_headers() {
+        let fake_yaml_content: &str = r#"
+        table: news
+        fetch:
+            headers: {Accept-Language: fr, X-Test: global}
+        sources:
+            - {url: https://www.cnews.fr/, selector: h3, headers: {X-Test: source}}
+        unknown_key: 1
+        "#;
+        let config = Config::new_from_yaml_string(fake_yaml_content, &false, &None, &false).unwrap();
+        assert_eq!(config.table, "news");
+        let headers = config.fetch_settings_for(&config.url_selectors[0]).headers;
+        assert_eq!(headers["Accept-Language"], "fr");
+        assert_eq!(headers["X-Test"], "source");
+        assert_eq!(config.warnings, vec![String::from("unknown key 'unknown_key' at line 7 is ignored")]);
+    }
+}
@@ -0,0 +1,895 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::Duration;
+
+use regex::Regex;
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+use super::config::{parse_csv_char, CsvQuoteStyle, CsvSettings, DbProfile, FetchOverrides, OutputSpec, FilterOverrides, HostLimits, PolitenessSettings,
+                    RetentionSettings, RobotsSettings, StorageSettings, TableOptions, UrlSelectorPair, WriterSettings};
+use super::errors::{Result, ScrapeError};
+use super::scrap_utils::{get_host_from_url, FieldSpec, FieldType, SelectorSpec};
+
+/// A number of seconds, fractions allowed
+#[derive(Debug, Clone, Copy)]
+struct Seconds(Duration);
+
+impl<'de> Deserialize<'de> for Seconds{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Seconds, D::Error>{
+        let secs = f64::deserialize(deserializer)?;
+        return Duration::try_from_secs_f64(secs).map(Seconds)
+                        .map_err(|_| de::Error::custom(format!("expected a positive number of seconds, got {}", secs)));
+    }
+}
+
+/// A concurrency limit or a size, at least 1
+#[derive(Debug, Clone, Copy)]
+struct Limit(usize);
+
+impl<'de> Deserialize<'de> for Limit{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Limit, D::Error>{
+        let limit = usize::deserialize(deserializer)?;
+        if limit == 0 {
+            return Err(de::Error::custom("limits and sizes must be at least 1"));
+        }
+        return Ok(Limit(limit));
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SourceUrl(String);
+
+impl<'de> Deserialize<'de> for SourceUrl{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<SourceUrl, D::Error>{
+        let url = String::deserialize(deserializer)?;
+        get_host_from_url(&url).map_err(de::Error::custom)?;
+        return Ok(SourceUrl(url));
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SourceSelector(String);
+
+impl<'de> Deserialize<'de> for SourceSelector{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<SourceSelector, D::Error>{
+        let selector = String::deserialize(deserializer)?;
+        SelectorSpec::parse(&selector).map_err(de::Error::custom)?;
+        return Ok(SourceSelector(selector));
+    }
+}
+
+impl SourceSelector{
+    /// The item selector of structured items is a plain CSS selector, the fields have the extractions
+    fn has_extraction_suffix(&self) -> bool{
+        return SelectorSpec::parse(&self.0).map(|spec| spec.css != self.0.trim()).unwrap_or(false);
+    }
+}
+
+/// Regexes, only checked here, they are compiled again by `ContentFilter`
+#[derive(Debug, Clone)]
+struct Patterns(Vec<String>);
+
+impl<'de> Deserialize<'de> for Patterns{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Patterns, D::Error>{
+        let patterns = Vec::<String>::deserialize(deserializer)?;
+        for pattern in &patterns {
+            Regex::new(pattern).map_err(|e| de::Error::custom(format!("invalid filter regex '{}': {}", pattern, e)))?;
+        }
+        return Ok(Patterns(patterns));
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Headers(BTreeMap<String, String>);
+
+impl<'de> Deserialize<'de> for Headers{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Headers, D::Error>{
+        let headers = BTreeMap::<String, String>::deserialize(deserializer)?;
+        for (name, value) in &headers {
+            HeaderName::from_bytes(name.as_bytes()).map_err(|_| de::Error::custom(format!("invalid header name '{}'", name)))?;
+            HeaderValue::from_str(value).map_err(|_| de::Error::custom(format!("invalid value for header '{}'", name)))?;
+        }
+        return Ok(Headers(headers));
+    }
+}
+
+/// SQLite pragmas, names and values are written in the PRAGMA statements so they are limited to words and numbers
+#[derive(Debug, Clone, Default)]
+struct Pragmas(BTreeMap<String, String>);
+
+impl<'de> Deserialize<'de> for Pragmas{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Pragmas, D::Error>{
+        let is_word = |v: &str| !v.is_empty() && v.chars().all(|c| c.is_ascii_alphabetic() || c == '_');
+        let is_number = |v: &str| v.strip_prefix('-').unwrap_or(v).parse::<u64>().is_ok();
+        let pragmas = BTreeMap::<String, serde_yaml::Value>::deserialize(deserializer)?;
+        let mut checked = BTreeMap::new();
+        for (name, value) in pragmas {
+            let value = match value {
+                serde_yaml::Value::String(v) => v,
+                serde_yaml::Value::Number(v) => v.to_string(),
+                serde_yaml::Value::Bool(v) => v.to_string(),
+                _ => return Err(de::Error::custom(format!("invalid value for pragma '{}', expected a word or a number", name)))
+            };
+            if !is_word(&name) {
+                return Err(de::Error::custom(format!("invalid pragma name '{}'", name)));
+            }
+            if !is_word(&value) && !is_number(&value) {
+                return Err(de::Error::custom(format!("invalid value '{}' for pragma '{}', expected a word or a number", value, name)));
+            }
+            checked.insert(name.to_lowercase(), value);
+        }
+        return Ok(Pragmas(checked));
+    }
+}
+
+/// A field of a structured item, either a selector or `{selector: span.views, type: integer}`
+#[derive(Debug, Clone)]
+struct FieldEntry{
+    selector: String,
+    field_type: FieldType
+}
+
+#[derive(Deserialize)]
+struct FieldEntryMap{
+    selector: String,
+    #[serde(rename = "type", default)]
+    field_type: Option<String>
+}
+
+impl<'de> Deserialize<'de> for FieldEntry{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<FieldEntry, D::Error>{
+        struct FieldEntryVisitor;
+
+        impl<'de> Visitor<'de> for FieldEntryVisitor{
+            type Value = FieldEntry;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result{
+                return f.write_str("a selector or a mapping with a selector and a type");
+            }
+
+            fn visit_str<E: de::Error>(self, selector: &str) -> std::result::Result<FieldEntry, E>{
+                return Ok(FieldEntry{selector: selector.to_string(), field_type: FieldType::Text});
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> std::result::Result<FieldEntry, A::Error>{
+                let entry = FieldEntryMap::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                let field_type = match entry.field_type {
+                    Some(t) => FieldType::parse(&t).map_err(de::Error::custom)?,
+                    None => FieldType::Text
+                };
+                return Ok(FieldEntry{selector: entry.selector, field_type});
+            }
+        }
+
+        return deserializer.deserialize_any(FieldEntryVisitor);
+    }
+}
+
+/// Fields of a structured item, in the file order which is also the columns order
+#[derive(Debug, Clone, Default)]
+struct Fields(Vec<FieldSpec>);
+
+impl<'de> Deserialize<'de> for Fields{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Fields, D::Error>{
+        struct FieldsVisitor;
+
+        impl<'de> Visitor<'de> for FieldsVisitor{
+            type Value = Fields;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result{
+                return f.write_str("a mapping of field names to selectors");
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<Fields, A::Error>{
+                let mut fields = Vec::new();
+                while let Some(name) = map.next_key::<String>()? {
+                    let entry: FieldEntry = map.next_value()?;
+                    if fields.iter().any(|f: &FieldSpec| f.name.eq_ignore_ascii_case(&name)) {
+                        return Err(de::Error::custom(format!("duplicate field name '{}', names are case-insensitive", name)));
+                    }
+                    fields.push(FieldSpec::new(&name, &entry.selector, entry.field_type).map_err(de::Error::custom)?);
+                }
+                return Ok(Fields(fields));
+            }
+        }
+
+        return deserializer.deserialize_map(FieldsVisitor);
+    }
+}
+
+/// ```yaml
+/// fetch:
+///   connect_timeout: 10
+///   read_timeout: 30
+///   retries: 3
+///   backoff_base: 0.5
+///   backoff_max: 30
+///   retry_statuses: [408, 429, 500, 502, 503, 504]
+///   headers: {Accept-Language: fr}
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct FetchFile{
+    connect_timeout: Option<Seconds>,
+    read_timeout: Option<Seconds>,
+    retries: Option<u32>,
+    backoff_base: Option<Seconds>,
+    backoff_max: Option<Seconds>,
+    retry_statuses: Option<Vec<u16>>,
+    headers: Option<Headers>
+}
+
+impl FetchFile{
+    fn overrides(&self) -> FetchOverrides{
+        return FetchOverrides{
+            connect_timeout: self.connect_timeout.map(|s| s.0),
+            read_timeout: self.read_timeout.map(|s| s.0),
+            retries: self.retries,
+            backoff_base: self.backoff_base.map(|s| s.0),
+            backoff_max: self.backoff_max.map(|s| s.0),
+            retry_statuses: self.retry_statuses.clone(),
+            headers: self.headers.clone().map(|h| h.0)
+        };
+    }
+}
+
+/// A length filter in characters, `none` clears the inherited one
+#[derive(Debug, Clone, Copy)]
+struct LengthLimit(Option<usize>);
+
+impl<'de> Deserialize<'de> for LengthLimit{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<LengthLimit, D::Error>{
+        struct LengthVisitor;
+
+        impl<'de> Visitor<'de> for LengthVisitor{
+            type Value = LengthLimit;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result{
+                return f.write_str("a number of characters or none");
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> std::result::Result<LengthLimit, E>{
+                return usize::try_from(value).map(|v| LengthLimit(Some(v))).map_err(E::custom);
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> std::result::Result<LengthLimit, E>{
+                return usize::try_from(value).map(|v| LengthLimit(Some(v)))
+                                             .map_err(|_| E::custom(format!("expected a number of characters, got {}", value)));
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> std::result::Result<LengthLimit, E>{
+                return match value {
+                    "none" => Ok(LengthLimit(None)),
+                    _ => Err(E::invalid_value(de::Unexpected::Str(value), &self))
+                };
+            }
+        }
+
+        return deserializer.deserialize_any(LengthVisitor);
+    }
+}
+
+/// ```yaml
+/// filters:
+///   include: ['^\p{Lu}']       # at least one must match, all items kept if empty
+///   exclude: ['^Publicité']
+///   min_length: 4              # none to keep the items of any length
+///   max_length: 300
+///   normalize_whitespace: true
+/// ```
+/// The filters only apply to the items of a selector, not to the fields of structured items.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct FilterFile{
+    include: Option<Patterns>,
+    exclude: Option<Patterns>,
+    min_length: Option<LengthLimit>,
+    max_length: Option<LengthLimit>,
+    normalize_whitespace: Option<bool>
+}
+
+impl FilterFile{
+    fn overrides(&self) -> FilterOverrides{
+        return FilterOverrides{
+            include: self.include.clone().map(|p| p.0),
+            exclude: self.exclude.clone().map(|p| p.0),
+            min_length: self.min_length.map(|l| l.0),
+            max_length: self.max_length.map(|l| l.0),
+            normalize_whitespace: self.normalize_whitespace
+        };
+    }
+
+    fn is_empty(&self) -> bool{
+        return self.include.is_none() && self.exclude.is_none() && self.min_length.is_none() && self.max_length.is_none()
+               && self.normalize_whitespace.is_none();
+    }
+}
+
+/// ```yaml
+/// politeness:
+///   max_concurrency: 16
+///   max_in_flight: 2      # per host default
+///   min_delay: 0.5        # per host default
+///   hosts:
+///     www.cnews.fr: {max_in_flight: 1, min_delay: 2}
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct PolitenessFile{
+    max_concurrency: Option<Limit>,
+    max_in_flight: Option<Limit>,
+    min_delay: Option<Seconds>,
+    hosts: BTreeMap<String, HostLimitsFile>
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct HostLimitsFile{
+    max_in_flight: Option<Limit>,
+    min_delay: Option<Seconds>
+}
+
+impl HostLimitsFile{
+    fn limits(&self, defaults: &HostLimits) -> HostLimits{
+        return HostLimits{max_in_flight: self.max_in_flight.map(|l| l.0).unwrap_or(defaults.max_in_flight),
+                          min_delay: self.min_delay.map(|s| s.0).unwrap_or(defaults.min_delay)};
+    }
+}
+
+/// ```yaml
+/// robots:
+///   enabled: true
+///   user_agent: web_scrap_cli/0.1.0
+///   ignore_hosts: [www.my-own-site.fr]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct RobotsFile{
+    enabled: Option<bool>,
+    user_agent: Option<String>,
+    ignore_hosts: Vec<String>
+}
+
+/// ```yaml
+/// storage:
+///   fts: true                  # defaults for every table
+///   dedup: true
+///   tables:
+///     cnews_article: {fts: false}
+///   profile: durable           # or fast_unsafe
+///   pragmas: {synchronous: FULL}
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct StorageFile{
+    fts: Option<bool>,
+    dedup: Option<bool>,
+    tables: BTreeMap<String, TableOptionsFile>,
+    profile: Option<DbProfile>,
+    pragmas: Pragmas
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct TableOptionsFile{
+    fts: Option<bool>,
+    dedup: Option<bool>
+}
+
+impl TableOptionsFile{
+    fn options(&self, defaults: &TableOptions) -> TableOptions{
+        return TableOptions{fts: self.fts.unwrap_or(defaults.fts), dedup: self.dedup.unwrap_or(defaults.dedup)};
+    }
+}
+
+/// A csv delimiter or quote, a single character
+#[derive(Debug, Clone, Copy)]
+struct CsvChar(u8);
+
+impl<'de> Deserialize<'de> for CsvChar{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<CsvChar, D::Error>{
+        let value = String::deserialize(deserializer)?;
+        return parse_csv_char(&value, "character").map(CsvChar).map_err(de::Error::custom);
+    }
+}
+
+/// ```yaml
+/// csv:
+///   dir: exports
+///   name: "{year}/{month}/{day}/{host}.csv"   # default to records_{host}_{run_id}_{index}.csv
+///   delimiter: ";"
+///   quote: "'"
+///   quote_style: always        # necessary (default), always, non_numeric or never
+///   append: true
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct CsvFile{
+    dir: Option<String>,
+    name: Option<String>,
+    delimiter: Option<CsvChar>,
+    quote: Option<CsvChar>,
+    quote_style: Option<CsvQuoteStyle>,
+    append: Option<bool>
+}
+
+/// ```yaml
+/// retention:
+///   keep_days: 90
+///   keep_runs: 10          # per url
+///   dedup: true
+///   vacuum: false
+///   after_scrape: true     # prune the database at the end of each scrape, `prune` only otherwise
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct RetentionFile{
+    keep_days: Option<u64>,
+    keep_runs: Option<u64>,
+    dedup: bool,
+    vacuum: bool,
+    after_scrape: bool
+}
+
+/// ```yaml
+/// writer:
+///   batch_size: 500        # records per commit
+///   flush_interval: 2      # seconds
+///   queue_size: 32         # pages waiting for the writer
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct WriterFile{
+    batch_size: Option<Limit>,
+    flush_interval: Option<Seconds>,
+    queue_size: Option<Limit>
+}
+
+/// An entry of the `sources` list, also used for the `structured_items` one where the selector is named `item`
+/// ```yaml
+/// sources:
+///   - url: https://www.cnews.fr/
+///     selector: h3
+///     headers: {Accept-Language: fr}
+///     filters: {min_length: 10}
+///     fetch: {retries: 5}
+///   - url: https://www.cnews.fr/
+///     selector: article
+///     table: cnews_article      # required with fields
+///     fields:
+///       title: h2
+///       link: a@href
+///       views: {selector: span.views, type: integer}
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+struct SourceFile{
+    url: SourceUrl,
+    #[serde(alias = "item")]
+    selector: SourceSelector,
+    #[serde(default)]
+    table: Option<String>,
+    #[serde(default)]
+    headers: Option<Headers>,
+    #[serde(default)]
+    fetch: FetchFile,
+    #[serde(default)]
+    filters: FilterFile,
+    #[serde(default)]
+    fields: Fields
+}
+
+impl SourceFile{
+    fn url_selector(&self) -> UrlSelectorPair{
+        let mut url_selector = UrlSelectorPair::new(&self.url.0, &self.selector.0);
+        url_selector.fetch = self.fetch.overrides();
+        if let Some(headers) = &self.headers {
+            url_selector.fetch.headers.get_or_insert_with(BTreeMap::new).extend(headers.0.clone());
+        }
+        url_selector.filter = self.filters.overrides();
+        url_selector.fields = self.fields.0.clone();
+        url_selector.table = self.table.clone();
+        return url_selector;
+    }
+}
+
+/// Optional third item of an `url_selector_tuples` entry, fetch and filter settings for this tuple only
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct TupleOptions{
+    connect_timeout: Option<Seconds>,
+    read_timeout: Option<Seconds>,
+    retries: Option<u32>,
+    backoff_base: Option<Seconds>,
+    backoff_max: Option<Seconds>,
+    retry_statuses: Option<Vec<u16>>,
+    headers: Option<Headers>,
+    include: Option<Patterns>,
+    exclude: Option<Patterns>,
+    min_length: Option<LengthLimit>,
+    max_length: Option<LengthLimit>,
+    normalize_whitespace: Option<bool>
+}
+
+/// `[url, selector]` or `[url, selector, {options}]`
+#[derive(Debug, Clone)]
+struct TupleEntry{
+    url: SourceUrl,
+    selector: SourceSelector,
+    options: TupleOptions
+}
+
+impl<'de> Deserialize<'de> for TupleEntry{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<TupleEntry, D::Error>{
+        struct TupleVisitor;
+
+        impl<'de> Visitor<'de> for TupleVisitor{
+            type Value = TupleEntry;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result{
+                return f.write_str("a [url, selector] or [url, selector, {options}] list");
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<TupleEntry, A::Error>{
+                let url = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let selector = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let options = seq.next_element()?.unwrap_or_default();
+                if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::invalid_length(4, &self));
+                }
+                return Ok(TupleEntry{url, selector, options});
+            }
+        }
+
+        return deserializer.deserialize_seq(TupleVisitor);
+    }
+}
+
+impl TupleEntry{
+    fn url_selector(&self) -> UrlSelectorPair{
+        let options = &self.options;
+        let fetch = FetchFile{connect_timeout: options.connect_timeout, read_timeout: options.read_timeout, retries: options.retries,
+                              backoff_base: options.backoff_base, backoff_max: options.backoff_max,
+                              retry_statuses: options.retry_statuses.clone(), headers: options.headers.clone()};
+        let filters = FilterFile{include: options.include.clone(), exclude: options.exclude.clone(), min_length: options.min_length,
+                                 max_length: options.max_length, normalize_whitespace: options.normalize_whitespace};
+        let mut url_selector = UrlSelectorPair::new(&self.url.0, &self.selector.0);
+        url_selector.fetch = fetch.overrides();
+        url_selector.filter = filters.overrides();
+        return url_selector;
+    }
+}
+
+/// Lines of the top level keys and of the entries of the top level lists, to locate
+/// the errors found after deserialization and the unknown keys.
+#[derive(Debug, Clone, Default)]
+struct LineIndex{
+    keys: HashMap<String, usize>,
+    entries: HashMap<String, Vec<usize>>
+}
+
+enum Frame{
+    Map{expect_key: bool},
+    Seq
+}
+
+#[derive(Default)]
+struct LineCollector{
+    index: LineIndex,
+    stack: Vec<Frame>,
+    top_key: Option<String>
+}
+
+impl MarkedEventReceiver for LineCollector{
+    fn on_event(&mut self, ev: Event, mark: Marker){
+        let is_node = matches!(ev, Event::Scalar(..) | Event::SequenceStart(_) | Event::MappingStart(_) | Event::Alias(_));
+        let depth = self.stack.len();
+        if is_node {
+            match self.stack.last_mut() {
+                Some(Frame::Map{expect_key}) => {
+                    if let (true, 1, Event::Scalar(key, ..)) = (*expect_key, depth, &ev) {
+                        self.index.keys.insert(key.clone(), mark.line());
+                        self.top_key = Some(key.clone());
+                    }
+                    *expect_key = !*expect_key;
+                },
+                Some(Frame::Seq) if depth == 2 => {
+                    if let Some(key) = &self.top_key {
+                        self.index.entries.entry(key.clone()).or_default().push(mark.line());
+                    }
+                },
+                _ => {}
+            }
+        }
+        match ev {
+            Event::MappingStart(_) => self.stack.push(Frame::Map{expect_key: true}),
+            Event::SequenceStart(_) => self.stack.push(Frame::Seq),
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+            },
+            _ => {}
+        }
+    }
+}
+
+impl LineIndex{
+    /// Syntax errors are ignored here, they are reported by serde_yaml
+    fn new(content: &str) -> LineIndex{
+        let mut collector = LineCollector::default();
+        let _ = Parser::new(content.chars()).load(&mut collector, false);
+        return collector.index;
+    }
+
+    fn entry_line(&self, list: &str, index: usize) -> Option<usize>{
+        return self.entries.get(list).and_then(|lines| lines.get(index)).copied();
+    }
+}
+
+/// Path of an unknown key, like `sources[0].fetch.retry`, with the line of its top level key or list entry
+fn describe_ignored_key(path: &serde_ignored::Path, lines: &LineIndex) -> String{
+    fn segments(path: &serde_ignored::Path, out: &mut Vec<(String, Option<usize>)>){
+        match path {
+            serde_ignored::Path::Root => {},
+            serde_ignored::Path::Seq{parent, index} => {
+                segments(parent, out);
+                out.push((String::new(), Some(*index)));
+            },
+            serde_ignored::Path::Map{parent, key} => {
+                segments(parent, out);
+                out.push((key.clone(), None));
+            },
+            serde_ignored::Path::Some{parent}
+            | serde_ignored::Path::NewtypeStruct{parent}
+            | serde_ignored::Path::NewtypeVariant{parent} => segments(parent, out)
+        }
+    }
+    let mut parts = Vec::new();
+    segments(path, &mut parts);
+
+    let mut text = String::new();
+    for (key, index) in &parts {
+        match index {
+            Some(i) => text.push_str(&format!("[{}]", i)),
+            None if text.is_empty() => text.push_str(key),
+            None => text.push_str(&format!(".{}", key))
+        }
+    }
+    let line = match parts.as_slice() {
+        [(list, None), (_, Some(i)), ..] => lines.entry_line(list, *i),
+        [(key, None), ..] => lines.keys.get(key).copied(),
+        _ => None
+    };
+    return match line {
+        Some(line) => format!("unknown key '{}' at line {} is ignored", text, line),
+        None => format!("unknown key '{}' is ignored", text)
+    };
+}
+
+/// Schema of the yaml config file, see `web_scrap_cli_cfg.yaml`.
+/// Urls come from `sources`, and for older files from `url_selector_tuples` and `structured_items`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ConfigFile{
+    pub db: Option<String>,
+    pub table: Option<String>,
+    pub to_csv: bool,
+    fetch: FetchFile,
+    politeness: PolitenessFile,
+    robots: RobotsFile,
+    filters: FilterFile,
+    storage: StorageFile,
+    csv: CsvFile,
+    retention: RetentionFile,
+    writer: WriterFile,
+    pub outputs: Vec<OutputSpec>,
+    sources: Vec<SourceFile>,
+    url_selector_tuples: Vec<TupleEntry>,
+    structured_items: Vec<SourceFile>,
+    #[serde(skip)]
+    lines: LineIndex,
+    /// Unknown keys found in the file
+    #[serde(skip)]
+    pub warnings: Vec<String>
+}
+
+const ITEM_EXTRACTION_ERROR: &str = "the item selector of a structured item can't have an extraction suffix like @href or ::html, the fields have them";
+
+impl ConfigFile{
+    pub fn parse(content: &str) -> Result<ConfigFile>{
+        let lines = LineIndex::new(content);
+        let mut ignored_keys = Vec::new();
+        let mut config_file: ConfigFile = if content.trim().is_empty() {
+            ConfigFile::default()
+        } else {
+            let deserializer = serde_yaml::Deserializer::from_str(content);
+            serde_ignored::deserialize(deserializer, |path| ignored_keys.push(describe_ignored_key(&path, &lines)))
+                .map_err(|e| ScrapeError::Config(e.to_string()))?
+        };
+        config_file.lines = lines;
+        config_file.warnings = ignored_keys;
+        return Ok(config_file);
+    }
+
+    fn entry_error(&self, list: &str, index: usize, url: &str, message: &str) -> ScrapeError{
+        let line = match self.lines.entry_line(list, index) {
+            Some(line) => format!(" at line {}", line),
+            None => String::new()
+        };
+        return ScrapeError::Config(format!("{}[{}] ('{}'){}: {}", list, index, url, line, message));
+    }
+
+    /// All the pairs of the file, `sources` first
+    pub fn url_selectors(&self) -> Result<Vec<UrlSelectorPair>>{
+        if self.sources.is_empty() && self.url_selector_tuples.is_empty() && self.structured_items.is_empty() {
+            return Err(ScrapeError::Config(String::from("no url to scrap, expected a 'sources' or 'url_selector_tuples' list")));
+        }
+        let mut url_selectors = Vec::new();
+        for (i, source) in self.sources.iter().enumerate() {
+            if !source.fields.0.is_empty() && source.table.is_none() {
+                return Err(self.entry_error("sources", i, &source.url.0, "a source with fields needs a 'table'"));
+            }
+            if !source.fields.0.is_empty() && !source.filters.is_empty() {
+                return Err(self.entry_error("sources", i, &source.url.0, "filters don't apply to structured items"));
+            }
+            if !source.fields.0.is_empty() && source.selector.has_extraction_suffix() {
+                return Err(self.entry_error("sources", i, &source.url.0, ITEM_EXTRACTION_ERROR));
+            }
+            url_selectors.push(source.url_selector());
+        }
+        url_selectors.extend(self.url_selector_tuples.iter().map(|t| t.url_selector()));
+        for (i, item) in self.structured_items.iter().enumerate() {
+            if item.fields.0.is_empty() {
+                return Err(self.entry_error("structured_items", i, &item.url.0, "a structured item needs 'fields'"));
+            }
+            if item.table.is_none() {
+                return Err(self.entry_error("structured_items", i, &item.url.0, "a structured item needs a 'table'"));
+            }
+            if !item.filters.is_empty() {
+                return Err(self.entry_error("structured_items", i, &item.url.0, "filters don't apply to structured items"));
+            }
+            if item.selector.has_extraction_suffix() {
+                return Err(self.entry_error("structured_items", i, &item.url.0, ITEM_EXTRACTION_ERROR));
+            }
+            url_selectors.push(item.url_selector());
+        }
+        return Ok(url_selectors);
+    }
+
+    pub fn fetch_overrides(&self) -> FetchOverrides{
+        return self.fetch.overrides();
+    }
+
+    pub fn filter_overrides(&self) -> FilterOverrides{
+        return self.filters.overrides();
+    }
+
+    pub fn politeness_settings(&self) -> PolitenessSettings{
+        let mut settings = PolitenessSettings::default();
+        if let Some(limit) = self.politeness.max_concurrency {
+            settings.max_concurrency = limit.0;
+        }
+        let host_defaults = HostLimitsFile{max_in_flight: self.politeness.max_in_flight, min_delay: self.politeness.min_delay};
+        settings.default_host_limits = host_defaults.limits(&settings.default_host_limits);
+        for (host, limits) in &self.politeness.hosts {
+            settings.hosts.insert(host.clone(), limits.limits(&settings.default_host_limits));
+        }
+        return settings;
+    }
+
+    pub fn storage_settings(&self) -> StorageSettings{
+        let mut settings = StorageSettings::default();
+        let table_defaults = TableOptionsFile{fts: self.storage.fts, dedup: self.storage.dedup};
+        settings.default_table_options = table_defaults.options(&settings.default_table_options);
+        for (table, options) in &self.storage.tables {
+            settings.tables.insert(table.clone(), options.options(&settings.default_table_options));
+        }
+        settings.profile = self.storage.profile.unwrap_or_default();
+        settings.pragmas = self.storage.pragmas.0.clone();
+        return settings;
+    }
+
+    pub fn csv_settings(&self) -> CsvSettings{
+        let mut settings = CsvSettings::default();
+        if let Some(dir) = &self.csv.dir {
+            settings.dir = dir.clone();
+        }
+        if let Some(name) = &self.csv.name {
+            settings.name = name.clone();
+        }
+        if let Some(delimiter) = self.csv.delimiter {
+            settings.delimiter = delimiter.0;
+        }
+        if let Some(quote) = self.csv.quote {
+            settings.quote = quote.0;
+        }
+        settings.quote_style = self.csv.quote_style.unwrap_or_default();
+        settings.append = self.csv.append.unwrap_or(settings.append);
+        return settings;
+    }
+
+    pub fn retention_settings(&self) -> RetentionSettings{
+        return RetentionSettings{keep_days: self.retention.keep_days, keep_runs: self.retention.keep_runs, dedup: self.retention.dedup,
+                                 vacuum: self.retention.vacuum, after_scrape: self.retention.after_scrape};
+    }
+
+    pub fn writer_settings(&self) -> WriterSettings{
+        let default = WriterSettings::default();
+        return WriterSettings{batch_size: self.writer.batch_size.map(|l| l.0).unwrap_or(default.batch_size),
+                              flush_interval: self.writer.flush_interval.map(|s| s.0).unwrap_or(default.flush_interval),
+                              queue_size: self.writer.queue_size.map(|l| l.0).unwrap_or(default.queue_size)};
+    }
+
+    pub fn robots_settings(&self) -> RobotsSettings{
+        let mut settings = RobotsSettings::default();
+        if let Some(enabled) = self.robots.enabled {
+            settings.enabled = enabled;
+        }
+        if let Some(user_agent) = &self.robots.user_agent {
+            settings.user_agent = user_agent.clone();
+        }
+        settings.ignore_hosts = self.robots.ignore_hosts.clone();
+        return settings;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sources() {
+        let content: &str = r#"
+table: news
+sources:
+  - url: https://www.cnews.fr/
+    selector: h3
+    headers: {Accept-Language: fr}
+    filters: {min_length: 10}
+  - url: https://www.cnews.fr/
+    selector: article
+    table: cnews_article
+    fields:
+      title: h2
+      views: {selector: span.views, type: integer}
+"#;
+        let config_file = ConfigFile::parse(content).unwrap();
+        assert_eq!(config_file.table, Some(String::from("news")));
+        assert!(config_file.warnings.is_empty(), "{:?}", config_file.warnings);
+        let url_selectors = config_file.url_selectors().unwrap();
+        assert_eq!(url_selectors[0].fetch.headers.as_ref().unwrap()["Accept-Language"], "fr");
+        assert_eq!(url_selectors[0].filter.min_length, Some(Some(10)));
+        assert_eq!(url_selectors[1].fields.iter().map(|f| f.name.as_str()).collect::<Vec<&str>>(), vec!["title", "views"]);
+        assert_eq!(url_selectors[1].table, Some(String::from("cnews_article")));
+    }
+
+    #[test]
+    fn test_parse_errors_name_entry_and_line() {
+        let bad_selector: &str = "sources:\n  - {url: https://www.cnews.fr/, selector: h3}\n  - {url: https://www.cnews.fr/, selector: 'div >'}\n";
+        let error = ConfigFile::parse(bad_selector).unwrap_err().to_string();
+        assert!(error.contains("sources[1]") && error.contains("line 3"), "{}", error);
+
+        let missing_table: &str = "sources:\n  - {url: https://www.cnews.fr/, selector: h3}\n  - {url: https://www.cnews.fr/, selector: article, fields: {title: h2}}\n";
+        let error = ConfigFile::parse(missing_table).unwrap().url_selectors().unwrap_err().to_string();
+        assert!(error.contains("sources[1] ('https://www.cnews.fr/') at line 3"), "{}", error);
+
+        for item in ["'div.card@href'", "'li::html'", "'li::text'"] {
+            let item_extraction = format!("structured_items:\n  - {{url: https://www.cnews.fr/, item: {}, table: cards, fields: {{title: h2}}}}\n", item);
+            let error = ConfigFile::parse(&item_extraction).unwrap().url_selectors().unwrap_err().to_string();
+            assert!(error.contains("structured_items[0] ('https://www.cnews.fr/') at line 2") && error.contains("extraction suffix"), "{}", error);
+        }
+        let source_extraction = "sources:\n  - {url: https://www.cnews.fr/, selector: 'article@id', table: cards, fields: {title: h2}}\n";
+        assert!(ConfigFile::parse(source_extraction).unwrap().url_selectors().is_err());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_warnings() {
+        let content: &str = "retries: 2\nsources:\n  - url: https://www.cnews.fr/\n    selector: h3\n    fetch: {retry: 5}\nurl_selector_tuples:\n  - [https://www.google.fr, div, {min_lenght: 2}]\n";
+        let config_file = ConfigFile::parse(content).unwrap();
+        assert_eq!(config_file.warnings, vec![String::from("unknown key 'retries' at line 1 is ignored"),
+                                              String::from("unknown key 'sources[0].fetch.retry' at line 3 is ignored"),
+                                              String::from("unknown key 'url_selector_tuples[0][2].min_lenght' at line 7 is ignored")]);
+    }
+}
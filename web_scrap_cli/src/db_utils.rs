@@ -1,10 +1,79 @@
-use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
 
-use rusqlite::{params, Connection, Result};
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, ToSql};
+use rusqlite::types::{ToSqlOutput, Value};
 
-use super::scrap_utils::SelectorRecord;
+use super::config::{StorageSettings, TableOptions};
+use super::scrap_utils::{FetchInfo, FieldSpec, FieldValue, SelectorRecord, StructuredRecord};
+use super::errors::{Result, ScrapeError};
+use super::migrations::{check_schema, migrate, register_record_table, FETCHES_TABLE, INTERNAL_TABLES, RUNS_TABLE, SELECTOR_KIND, STRUCTURED_KIND};
 
-fn create_selector_record_table(conn: &Connection, table_name: &str) -> Result<(), Box<dyn Error>> {
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Opens a database that must already exist, `Connection::open` would create an empty one.
+/// Its schema isn't checked, see `open_existing_db`.
+pub fn open_db_file(db_path: &str, writable: bool) -> Result<Connection>{
+    if !Path::new(db_path).is_file() {
+        return Err(ScrapeError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, format!("database '{}' not found", db_path))));
+    }
+    let flags = if writable { OpenFlags::SQLITE_OPEN_READ_WRITE } else { OpenFlags::SQLITE_OPEN_READ_ONLY };
+    let conn = Connection::open_with_flags(db_path, flags)?;
+    // waits for a scrape commit instead of failing, a WAL database can be read during a scrape
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    return Ok(conn);
+}
+
+/// Opens an existing database, a writable one is migrated to the latest schema,
+/// a read-only one must already have it
+pub fn open_existing_db(db_path: &str, writable: bool) -> Result<Connection>{
+    let mut conn = open_db_file(db_path, writable)?;
+    if writable {
+        migrate(&mut conn)?;
+    } else {
+        check_schema(&conn)?;
+    }
+    return Ok(conn);
+}
+
+/// Opens or creates a database with the pragmas of the settings and applies the pending migrations
+pub fn open_db(db_path: &str, settings: &StorageSettings) -> Result<Connection>{
+    let mut conn = Connection::open(db_path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    let pragmas: Vec<String> = settings.pragmas().iter().map(|(name, value)| format!("PRAGMA {} = {};", name, value)).collect();
+    conn.execute_batch(&pragmas.join("\n"))?;
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    migrate(&mut conn)?;
+    return Ok(conn);
+}
+
+/// Quotes an identifier spliced in a statement, a double quote inside is doubled
+pub fn quote_ident(name: &str) -> String{
+    return format!("\"{}\"", name.replace('"', "\"\""));
+}
+
+const MAX_TABLE_NAME_LEN: usize = 64;
+
+/// Table names are always quoted, these rules keep them easy to type in sqlite3 or a shell
+/// and leave the names of the scraper own tables free
+pub fn check_table_name(name: &str) -> Result<()>{
+    let invalid = |reason: &str| Err(ScrapeError::Config(format!("invalid table name '{}': {}", name, reason)));
+    if name.is_empty() || name.chars().count() > MAX_TABLE_NAME_LEN {
+        return invalid(&format!("expected 1 to {} characters", MAX_TABLE_NAME_LEN));
+    }
+    if !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == ' ') {
+        return invalid("only letters, digits, '_', '-' and spaces are allowed");
+    }
+    // SQLite identifiers ignore the case of ascii letters
+    let lowercase = name.to_ascii_lowercase();
+    if lowercase.starts_with("sqlite_") || INTERNAL_TABLES.contains(&lowercase.as_str()) || lowercase.ends_with("_fts") {
+        return invalid("the name is reserved");
+    }
+    return Ok(());
+}
+
+/// A new table has the latest schema, the older ones are upgraded by the migrations
+fn create_selector_record_table(conn: &Connection, table_name: &str) -> Result<()> {
     conn.execute(
         format!("CREATE TABLE IF NOT EXISTS {} (
                   id              INTEGER PRIMARY KEY,
@@ -12,64 +81,242 @@ fn create_selector_record_table(conn: &Connection, table_name: &str) -> Result<(
                   url             TEXT NOT NULL,
                   selector        TEXT NOT NULL,
                   content         TEXT,
-                  host            TEXT NOT NULL
-                  )", table_name).as_str(),
+                  host            TEXT NOT NULL,
+                  extract         TEXT NOT NULL DEFAULT 'text',
+                  content_hash    TEXT,
+                  first_seen      INTEGER,
+                  last_seen       INTEGER,
+                  seen_count      INTEGER NOT NULL DEFAULT 1,
+                  fetch_id        INTEGER REFERENCES {}(id)
+                  )", quote_ident(table_name), FETCHES_TABLE).as_str(),
         [],
     )?;
+    conn.execute(format!("CREATE INDEX IF NOT EXISTS {} ON {} (content_hash)",
+                         quote_ident(&format!("{}_content_hash", table_name)), quote_ident(table_name)).as_str(), [])?;
+    register_record_table(conn, table_name, SELECTOR_KIND)?;
+    return Ok(());
+}
+
+/// Name of the FTS5 index of a selector record table
+pub fn fts_table_name(table_name: &str) -> String{
+    return format!("{}_fts", table_name);
+}
+
+pub fn has_fts_index(conn: &Connection, table_name: &str) -> Result<bool>{
+    let count: u32 = conn.query_row("SELECT COUNT(*) FROM sqlite_schema WHERE type = 'table' AND name = ?1",
+                                    [fts_table_name(table_name)], |row| row.get(0))?;
+    return Ok(count > 0);
+}
+
+/// External content FTS5 index on the content column, kept in sync by triggers so that
+/// every later insert, update or delete is indexed. Existing rows are indexed on creation.
+pub fn create_fts_index(conn: &Connection, table_name: &str) -> Result<()>{
+    check_table_name(table_name)?;
+    if has_fts_index(conn, table_name)? {
+        return Ok(());
+    }
+    let fts = fts_table_name(table_name);
+    // the table name check leaves no quote to escape in the content option
+    conn.execute_batch(format!("
+        CREATE VIRTUAL TABLE {fts} USING fts5(content, content='{content_table}', content_rowid='id');
+        CREATE TRIGGER IF NOT EXISTS {insert_trigger} AFTER INSERT ON {table} BEGIN
+            INSERT INTO {fts}(rowid, content) VALUES (new.id, new.content);
+        END;
+        CREATE TRIGGER IF NOT EXISTS {delete_trigger} AFTER DELETE ON {table} BEGIN
+            INSERT INTO {fts}({fts}, rowid, content) VALUES ('delete', old.id, old.content);
+        END;
+        CREATE TRIGGER IF NOT EXISTS {update_trigger} AFTER UPDATE OF content ON {table} BEGIN
+            INSERT INTO {fts}({fts}, rowid, content) VALUES ('delete', old.id, old.content);
+            INSERT INTO {fts}(rowid, content) VALUES (new.id, new.content);
+        END;
+        INSERT INTO {fts}({fts}) VALUES ('rebuild');",
+        fts = quote_ident(&fts), content_table = table_name, table = quote_ident(table_name),
+        insert_trigger = quote_ident(&format!("{}_insert", fts)), delete_trigger = quote_ident(&format!("{}_delete", fts)),
+        update_trigger = quote_ident(&format!("{}_update", fts))).as_str())?;
     return Ok(());
 }
 
-pub fn save_selector_records_to_db(conn: &mut Connection, table_name: &str, records: &Vec<SelectorRecord>) -> Result<(), Box<dyn Error>>{
+/// Number of records of a save, without dedup they are all new
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SaveOutcome{
+    pub new: usize,
+    pub seen_again: usize
+}
+
+impl SaveOutcome{
+    pub fn all_new(count: usize) -> SaveOutcome{
+        return SaveOutcome{new: count, seen_again: 0};
+    }
+
+    pub fn add(&mut self, other: SaveOutcome){
+        self.new += other.new;
+        self.seen_again += other.seen_again;
+    }
+}
+
+/// With `options.dedup`, a record with the same url, selector and content as a saved one only
+/// updates its `last_seen` and `seen_count`, including a duplicate inside `records`.
+/// `fetch_id` is the row of the `fetches` table of the page the records come from.
+/// The records are saved in a savepoint, committed with the batch transaction of the scrape when there's one.
+pub fn save_selector_records_to_db(conn: &mut Connection, table_name: &str, records: &[SelectorRecord], options: &TableOptions,
+                                   fetch_id: Option<i64>) -> Result<SaveOutcome>{
     
-    create_selector_record_table(&conn, table_name)?;
-    let transaction = conn.transaction().unwrap(); 
-    let stmt_template = format!("INSERT INTO {} (timestamp, url, selector, content, host) VALUES (?1, ?2, ?3, ?4, ?5)", table_name);
-    let mut stmt = transaction.prepare_cached(&stmt_template).unwrap();
+    check_table_name(table_name)?;
+    create_selector_record_table(conn, table_name)?;
+    if options.fts {
+        create_fts_index(conn, table_name)?;
+    }
+    let transaction = conn.savepoint()?;
+    let stmt_template = format!("INSERT INTO {} (timestamp, url, selector, content, host, extract, content_hash, first_seen, last_seen, fetch_id)
+                                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?1, ?1, ?8)", quote_ident(table_name));
+    let mut stmt = transaction.prepare_cached(&stmt_template)?;
+    // a table saved without dedup can hold the same record more than once, only the first row is seen again
+    let seen_template = format!("UPDATE {table} SET last_seen = ?1, seen_count = seen_count + 1
+                                 WHERE id = (SELECT MIN(id) FROM {table} WHERE content_hash = ?2)", table = quote_ident(table_name));
+    let mut seen_stmt = transaction.prepare_cached(&seen_template)?;
                             
+    let mut outcome = SaveOutcome::default();
     for record in records{
-        stmt.execute(params![record.timestamp, record.url, record.selector, record.content, record.host]).unwrap();
-    //     match &conn.execute(format!("INSERT INTO {} (timestamp, url, selector, content) VALUES (?1, ?2, ?3, ?4)", table_name).as_str(),
-    //                         params![record.timestamp, record.url, record.selector, record.content]) 
-    //     {
-    //         Ok(updated) => println!("{} row inserted", updated),
-    //         Err(err) => println!("update failed: {}", err),
-    //     }
+        let content_hash = record.content_hash();
+        if options.dedup && seen_stmt.execute(params![record.timestamp, content_hash])? > 0 {
+            outcome.seen_again += 1;
+            continue;
+        }
+        stmt.execute(params![record.timestamp, record.url, record.selector, record.content, record.host, record.extract, content_hash, fetch_id])?;
+        outcome.new += 1;
     }
     drop(stmt); // Added to release transaction ownership and solve the followng: borrow might be used here, when `stmt` is dropped and runs the `Drop` code for type `rusqlite::CachedStatement`
-    transaction.commit().unwrap();
+    drop(seen_stmt);
+    transaction.commit()?;
     
+    return Ok(outcome);
+}
+
+/// Inserts the records that aren't already in the table, a record with the same url, selector, content
+/// and timestamp as a saved one or an earlier one of `records` is a duplicate. Returns the number of inserted records.
+pub fn import_selector_records(conn: &mut Connection, table_name: &str, records: &[SelectorRecord]) -> Result<usize>{
+    check_table_name(table_name)?;
+    create_selector_record_table(conn, table_name)?;
+    let transaction = conn.transaction()?;
+    let insert_template = format!("INSERT INTO {} (timestamp, url, selector, content, host, extract, content_hash, first_seen, last_seen)
+                                   VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?1, ?1)", quote_ident(table_name));
+    let mut insert_stmt = transaction.prepare_cached(&insert_template)?;
+    let exists_template = format!("SELECT EXISTS (SELECT 1 FROM {} WHERE content_hash = ?1 AND timestamp = ?2)", quote_ident(table_name));
+    let mut exists_stmt = transaction.prepare_cached(&exists_template)?;
+
+    let mut inserted = 0;
+    for record in records {
+        let content_hash = record.content_hash();
+        if exists_stmt.query_row(params![content_hash, record.timestamp], |row| row.get::<_, bool>(0))? {
+            continue;
+        }
+        insert_stmt.execute(params![record.timestamp, record.url, record.selector, record.content, record.host, record.extract, content_hash])?;
+        inserted += 1;
+    }
+    drop(insert_stmt);
+    drop(exists_stmt);
+    transaction.commit()?;
+    return Ok(inserted);
+}
+
+impl ToSql for FieldValue {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let value = match self {
+            FieldValue::Null => Value::Null,
+            FieldValue::Text(v) => Value::Text(v.clone()),
+            FieldValue::Integer(v) => Value::Integer(*v),
+            FieldValue::Real(v) => Value::Real(*v),
+        };
+        return Ok(ToSqlOutput::Owned(value));
+    }
+}
+
+fn create_structured_record_table(conn: &Connection, table_name: &str, fields: &[FieldSpec]) -> Result<()> {
+    let field_columns: Vec<String> = fields.iter().map(|f| format!("{} {}", quote_ident(&f.name), f.field_type.sql_type())).collect();
+    conn.execute(
+        format!("CREATE TABLE IF NOT EXISTS {} (
+                  id              INTEGER PRIMARY KEY,
+                  timestamp       TIMESTAMP,
+                  url             TEXT NOT NULL,
+                  selector        TEXT NOT NULL,
+                  host            TEXT NOT NULL,
+                  fetch_id        INTEGER REFERENCES {}(id),
+                  {}
+                  )", quote_ident(table_name), FETCHES_TABLE, field_columns.join(",\n")).as_str(),
+        [],
+    )?;
+    register_record_table(conn, table_name, STRUCTURED_KIND)?;
+    // fields added to the config after the table creation
+    let existing_columns = get_col_names(conn, table_name)?;
+    for field in fields.iter().filter(|f| !existing_columns.contains(&f.name)) {
+        conn.execute(format!("ALTER TABLE {} ADD COLUMN {} {}", quote_ident(table_name), quote_ident(&field.name), field.field_type.sql_type()).as_str(), [])?;
+    }
     return Ok(());
 }
 
-pub fn get_selector_records_from_table(conn: &Connection, table: &str)-> Result<Vec<SelectorRecord>, Box<dyn Error>>{
-    let sql_request = format!("SELECT timestamp, url, selector, content FROM {}", table);
-    let mut stmt = conn.prepare(&sql_request)?;
-    let record_iter = stmt.query_map([], |row| {
-        Ok(SelectorRecord::new(row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
-    })?;
+/// Saved in a savepoint like the selector records
+pub fn save_structured_records_to_db(conn: &mut Connection, table_name: &str, fields: &[FieldSpec], records: &[StructuredRecord],
+                                     fetch_id: Option<i64>) -> Result<()>{
+    check_table_name(table_name)?;
+    create_structured_record_table(conn, table_name, fields)?;
+    let transaction = conn.savepoint()?;
+    let field_names: Vec<String> = fields.iter().map(|f| quote_ident(&f.name)).collect();
+    let placeholders: Vec<String> = (1..=field_names.len() + 5).map(|i| format!("?{}", i)).collect();
+    let stmt_template = format!("INSERT INTO {} (timestamp, url, selector, host, fetch_id, {}) VALUES ({})", 
+                                quote_ident(table_name), field_names.join(", "), placeholders.join(", "));
+    let mut stmt = transaction.prepare_cached(&stmt_template)?;
 
-    let mut records = Vec::new();
-    for record in record_iter {
-        println!("1 SelectorRecord loaded");
-       records.push(record?);
+    for record in records{
+        let mut values: Vec<&dyn ToSql> = vec![&record.timestamp, &record.url, &record.selector, &record.host, &fetch_id];
+        values.extend(record.fields.iter().map(|(_, v)| v as &dyn ToSql));
+        stmt.execute(params_from_iter(values))?;
     }
-    return Ok(records);
+    drop(stmt);
+    transaction.commit()?;
+    return Ok(());
 }
 
-pub fn get_db_table_names(conn: &Connection) -> Result<Vec<String>, Box<dyn Error>>{
-    let sql_request = "SELECT name FROM sqlite_schema WHERE type ='table' AND name NOT LIKE 'sqlite_%';";
-    let mut stmt = conn.prepare(&sql_request)?;
-    let rows = stmt.query_map([], |row| row.get(0))?;
+/// Adds the row of a new run and returns its id
+pub fn start_run(conn: &Connection, started_at: u64, config_hash: &str) -> Result<i64>{
+    conn.execute(&format!("INSERT INTO {} (started_at, config_hash) VALUES (?1, ?2)", RUNS_TABLE), params![started_at, config_hash])?;
+    return Ok(conn.last_insert_rowid());
+}
 
-    let mut values = Vec::new();
+pub fn finish_run(conn: &Connection, run_id: i64, ended_at: u64) -> Result<()>{
+    conn.execute(&format!("UPDATE {} SET ended_at = ?1 WHERE id = ?2", RUNS_TABLE), params![ended_at, run_id])?;
+    return Ok(());
+}
+
+/// Adds the row of a page fetch and returns its id
+pub fn save_fetch(conn: &Connection, run_id: i64, info: &FetchInfo) -> Result<i64>{
+    conn.execute(&format!("INSERT INTO {} (run_id, url, final_url, status_code, latency_ms, byte_size, content_type, fetched_at, attempts, error)
+                           VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)", FETCHES_TABLE),
+                 params![run_id, info.url, info.final_url, info.status_code, info.latency.as_millis() as u64, info.byte_size,
+                         info.content_type, info.fetched_at, info.attempts, info.error])?;
+    return Ok(conn.last_insert_rowid());
+}
+
+/// Record tables, the migration tables, the full-text indexes and their shadow tables are left out
+pub fn get_db_table_names(conn: &Connection) -> Result<Vec<String>>{
+    let sql_request = "SELECT name, sql LIKE 'CREATE VIRTUAL TABLE%' FROM sqlite_schema WHERE type ='table' AND name NOT LIKE 'sqlite_%';";
+    let mut stmt = conn.prepare(sql_request)?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, bool>(1)?)))?;
+
+    let mut tables = Vec::new();
     for val in rows {
-       values.push(val?);
+       tables.push(val?);
     }
+    let virtual_tables: Vec<String> = tables.iter().filter(|(_, is_virtual)| *is_virtual).map(|(name, _)| format!("{}_", name)).collect();
+    let values = tables.into_iter()
+                       .filter(|(name, is_virtual)| !is_virtual && !virtual_tables.iter().any(|v| name.starts_with(v.as_str())))
+                       .filter(|(name, _)| !INTERNAL_TABLES.contains(&name.as_str()))
+                       .map(|(name, _)| name)
+                       .collect();
     return Ok(values);
 }
 
-pub fn get_row_count(conn: &Connection, table: &str) -> Result<u32, Box<dyn Error>> {
-    let sql_request = format!("SELECT COUNT(*) FROM {}", table);
+pub fn get_row_count(conn: &Connection, table: &str) -> Result<u32> {
+    let sql_request = format!("SELECT COUNT(*) FROM {}", quote_ident(table));
 
     let mut stmt = conn.prepare(&sql_request)?;
     let rows = stmt.query_map([], |row| row.get(0))?;
@@ -82,11 +329,9 @@ pub fn get_row_count(conn: &Connection, table: &str) -> Result<u32, Box<dyn Erro
     return Ok(values[0]);
 }
 
-pub fn get_col_names(conn: &Connection, table: &str) -> Result<Vec<String>, Box<dyn Error>> {
-    let sql_request = format!("PRAGMA table_info({});", table);
-
-    let mut stmt = conn.prepare(&sql_request)?;
-    let rows = stmt.query_map([], |row| row.get(1))?; // get the second item for the column name
+pub fn get_col_names(conn: &Connection, table: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1) ORDER BY cid")?;
+    let rows = stmt.query_map([table], |row| row.get(0))?;
 
     let mut values = Vec::new();
     for val in rows {
@@ -95,24 +340,9 @@ pub fn get_col_names(conn: &Connection, table: &str) -> Result<Vec<String>, Box<
     return Ok(values);
 }
 
-pub fn _drop_table(conn: &Connection, table: &str) -> Result<(), Box<dyn Error>>{
-    match conn.execute(format!("DROP TABLE {}", table).as_str(), [],) {
-        Ok(_updated) => println!("'{}' has been dropped", table),
-        Err(err) => panic!("DROP TABLE failed: {}", err),
-    }
-    return Ok(());
-}
-
-pub fn print_db_stats(conn: &Connection) -> Result<(), Box<dyn Error>> {
-    println!("--------------\nDatabase Stats:");
-    let table_names = get_db_table_names(&conn)?;
-    for table in table_names{
-        println!(">> '{}' table :", table);
-        println!("Columns : {:?}", get_col_names(conn, &table)?);
-        println!("{:?} rows", get_row_count(conn, &table)?);  
-        let _records = get_selector_records_from_table(conn, &table);
-    }
-    println!("--------------");
+pub fn _drop_table(conn: &Connection, table: &str) -> Result<()>{
+    conn.execute(format!("DROP TABLE {}", quote_ident(table)).as_str(), [],)?;
+    println!("'{}' has been dropped", table);
     return Ok(());
 }
 
@@ -120,12 +350,16 @@ pub fn print_db_stats(conn: &Connection) -> Result<(), Box<dyn Error>> {
 mod tests {        
     use std::path::Path; 
 
-    use super::super::*;  // retrieve all from main
-    use super::get_row_count;
+    use super::super::*;  // retrieve all from the crate root
+    use super::*;
+    use super::super::migrations::migrate;
+    use super::super::config::{StorageSettings, TableOptions};
+    use rusqlite::Connection;
 
     #[test]
     fn test_save_selector_records_to_db() {
         let mut conn = Connection::open(Path::new(&"web_scrap_cli_test.db")).unwrap();
+        migrate(&mut conn).unwrap();
         let table = &"selector_record_test";
 
         match conn.execute(format!("DROP TABLE IF EXISTS {}", table).as_str(), [],) {
@@ -133,24 +367,152 @@ mod tests {
             Err(err) => panic!("DROP TABLE failed: {}", err),
         }
         
-        let mut records: Vec<SelectorRecord> = Vec::new();
-        records.push(SelectorRecord::new(get_timestamp_now(), 
-                                    String::from(r"http:\\www.test.fr"),
-                                    String::from("a"),
-                                    String::from("blablabla")
-                                ));
-        records.push(SelectorRecord::new(get_timestamp_now(), 
+        let records: Vec<SelectorRecord> = vec![
+            SelectorRecord::new(get_timestamp_now(), 
+                                String::from(r"http:\\www.test.fr"),
+                                String::from("a"),
+                                String::from("blablabla"),
+                                String::from("text")
+                            ),
+            SelectorRecord::new(get_timestamp_now(), 
                                 String::from(r"http:\\www.test-other.fr"),
                                 String::from("p"),
-                                String::from("bliblibli")
-                            ));
+                                String::from("bliblibli"),
+                                String::from("attr:href")
+                            )];
 
-        save_selector_records_to_db(&mut conn, table, &records).unwrap();
+        save_selector_records_to_db(&mut conn, table, &records, &TableOptions::default(), None).unwrap();
         let row_count = get_row_count(&conn, table).unwrap();        
         assert_eq!(row_count, 2, "{:?}", row_count);
 
-        let _result = print_db_stats(&conn);
         _drop_table(&conn, table).unwrap();
     }
 
+    #[test]
+    fn test_save_selector_records_with_dedup() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let table = "dedup_test";
+        let record = |timestamp: u64, content: &str| SelectorRecord::new(timestamp, String::from("https://www.cnews.fr/"), String::from("h3"),
+                                                                         content.to_string(), String::from("text"));
+        // a table saved before the dedup columns, its rows are hashed by the migration adding them
+        conn.execute("CREATE TABLE dedup_test (id INTEGER PRIMARY KEY, timestamp TIMESTAMP, url TEXT NOT NULL, selector TEXT NOT NULL,
+                      content TEXT, host TEXT NOT NULL)", []).unwrap();
+        conn.execute("INSERT INTO dedup_test (timestamp, url, selector, content, host) VALUES (50, 'https://www.cnews.fr/', 'h3', 'Old headline', 'www.cnews.fr')", []).unwrap();
+        migrate(&mut conn).unwrap();
+
+        let options = TableOptions{dedup: true, ..TableOptions::default()};
+        let outcome = save_selector_records_to_db(&mut conn, table, &[record(100, "Headline"), record(100, "Headline"), record(100, "Old headline")], &options, None).unwrap();
+        assert_eq!(outcome, SaveOutcome{new: 1, seen_again: 2});
+        let outcome = save_selector_records_to_db(&mut conn, table, &[record(200, "Headline"), record(200, "Other headline")], &options, None).unwrap();
+        assert_eq!(outcome, SaveOutcome{new: 1, seen_again: 1});
+
+        let (first_seen, last_seen, seen_count): (u64, u64, u32) = conn.query_row(
+            "SELECT first_seen, last_seen, seen_count FROM dedup_test WHERE content = 'Headline'", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap();
+        assert_eq!((first_seen, last_seen, seen_count), (100, 200, 3));
+        let old_seen: (u64, u32) = conn.query_row("SELECT first_seen, seen_count FROM dedup_test WHERE content = 'Old headline'", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        assert_eq!(old_seen, (50, 2));
+        assert_eq!(get_row_count(&conn, table).unwrap(), 3);
+
+        // without dedup every record is a new row
+        let outcome = save_selector_records_to_db(&mut conn, table, &[record(300, "Headline")], &TableOptions::default(), None).unwrap();
+        assert_eq!(outcome, SaveOutcome::all_new(1));
+
+        // with two rows of the same record, only the first one is updated
+        let outcome = save_selector_records_to_db(&mut conn, table, &[record(400, "Headline")], &options, None).unwrap();
+        assert_eq!(outcome, SaveOutcome{new: 0, seen_again: 1});
+        let mut stmt = conn.prepare("SELECT last_seen, seen_count FROM dedup_test WHERE content = 'Headline' ORDER BY id").unwrap();
+        let rows: Vec<(u64, u32)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(rows, vec![(400, 4), (300, 1)]);
+    }
+
+    #[test]
+    fn test_records_linked_to_run_and_fetch() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let run_id = start_run(&conn, 100, "config-hash").unwrap();
+        let info = FetchInfo{url: String::from("https://www.cnews.fr/"), fetched_at: 100, status_code: Some(200),
+                             final_url: Some(String::from("https://www.cnews.fr/accueil")), content_type: Some(String::from("text/html")),
+                             byte_size: Some(2048), latency: Duration::from_millis(120), attempts: 1, error: None};
+        let fetch_id = save_fetch(&conn, run_id, &info).unwrap();
+        let records = vec![SelectorRecord::new(100, String::from("https://www.cnews.fr/"), String::from("h3"), String::from("Headline"), String::from("text"))];
+        save_selector_records_to_db(&mut conn, "selector_record", &records, &TableOptions::default(), Some(fetch_id)).unwrap();
+        finish_run(&conn, run_id, 110).unwrap();
+
+        let (status, final_url, latency, ended_at): (u16, String, u64, u64) = conn.query_row(
+            "SELECT f.status_code, f.final_url, f.latency_ms, r.ended_at FROM selector_record s
+             JOIN fetches f ON f.id = s.fetch_id JOIN runs r ON r.id = f.run_id", [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))).unwrap();
+        assert_eq!((status, final_url.as_str(), latency, ended_at), (200, "https://www.cnews.fr/accueil", 120, 110));
+    }
+
+    #[test]
+    fn test_quoted_table_names() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let record = SelectorRecord::new(100, String::from("https://www.cnews.fr/"), String::from("h3"), String::from("Élections"), String::from("text"));
+        let options = TableOptions{fts: true, dedup: true};
+        save_selector_records_to_db(&mut conn, "cnews news-2024", &[record.clone(), record], &options, None).unwrap();
+        assert_eq!(get_row_count(&conn, "cnews news-2024").unwrap(), 1);
+        let content: String = conn.query_row("SELECT content FROM \"cnews news-2024\"", [], |row| row.get(0)).unwrap();
+        assert_eq!(content, "Élections");
+
+        // a field named like a keyword
+        let fields = vec![FieldSpec::new("order", "span", FieldType::Integer).unwrap()];
+        let records = vec![StructuredRecord{timestamp: 100, url: String::from("https://www.cnews.fr/"), selector: String::from("li"),
+                                            host: String::from("www.cnews.fr"), fields: vec![(String::from("order"), FieldValue::Integer(1))]}];
+        save_structured_records_to_db(&mut conn, "ranking-list", &fields, &records, None).unwrap();
+        assert_eq!(get_col_names(&conn, "ranking-list").unwrap().last().unwrap(), "order");
+
+        for name in ["", "x; DROP TABLE runs", "news\"", "runs", "sqlite_master", "news_fts", "Runs", "FETCHES", "RECORD_TABLES", "news_FTS"] {
+            assert!(matches!(check_table_name(name), Err(ScrapeError::Config(_))), "{}", name);
+        }
+        assert!(save_selector_records_to_db(&mut conn, "x; DROP TABLE runs", &[], &options, None).is_err());
+    }
+
+    #[test]
+    fn test_save_structured_records_to_db() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let table = "article_test";
+        let fields = vec![FieldSpec::new("title", "h2", FieldType::Text).unwrap(),
+                          FieldSpec::new("views", "span", FieldType::Integer).unwrap()];
+        let records = vec![StructuredRecord{timestamp: get_timestamp_now(),
+                                            url: String::from("https://www.cnews.fr/"),
+                                            selector: String::from("article"),
+                                            host: String::from("www.cnews.fr"),
+                                            fields: vec![(String::from("title"), FieldValue::Text(String::from("Title"))),
+                                                         (String::from("views"), FieldValue::Integer(12))]}];
+
+        save_structured_records_to_db(&mut conn, table, &fields, &records, None).unwrap();
+        // a new field adds a column to the existing table
+        let more_fields = vec![fields[0].clone(), fields[1].clone(), FieldSpec::new("link", "a@href", FieldType::Text).unwrap()];
+        let mut more_records = records.clone();
+        more_records[0].fields.push((String::from("link"), FieldValue::Null));
+        save_structured_records_to_db(&mut conn, table, &more_fields, &more_records, None).unwrap();
+
+        assert_eq!(get_row_count(&conn, table).unwrap(), 2);
+        let views: i64 = conn.query_row("SELECT SUM(views) FROM article_test", [], |row| row.get(0)).unwrap();
+        assert_eq!(views, 24);
+    }
+
+    #[test]
+    fn test_read_during_write_transaction() {
+        let path = std::env::temp_dir().join("web_scrap_cli_test_wal.db");
+        let path = path.to_str().unwrap();
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+        let mut conn = open_db(path, &StorageSettings::default()).unwrap();
+        let records = vec![SelectorRecord::new(100, String::from("https://www.cnews.fr/"), String::from("h3"), String::from("Headline"), String::from("text"))];
+        save_selector_records_to_db(&mut conn, "news", &records, &TableOptions::default(), None).unwrap();
+
+        // the Durable profile uses WAL : a reader sees the last commit while a write transaction is open
+        conn.execute_batch("BEGIN").unwrap();
+        save_selector_records_to_db(&mut conn, "news", &records, &TableOptions::default(), None).unwrap();
+        let reader = open_existing_db(path, false).unwrap();
+        assert_eq!(get_row_count(&reader, "news").unwrap(), 1);
+        conn.execute_batch("COMMIT").unwrap();
+        assert_eq!(get_row_count(&reader, "news").unwrap(), 2);
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,101 @@
+use std::fmt;
+
+/// Every failure the scraper can report, whatever the step that produced it.
+#[derive(Debug)]
+pub enum ScrapeError {
+    Network(reqwest::Error),
+    HttpStatus { url: String, status: reqwest::StatusCode },
+    SelectorParse { selector: String, reason: String },
+    InvalidUrl { url: String, reason: String },
+    RobotsDisallowed { url: String, user_agent: String },
+    Db(rusqlite::Error),
+    Csv(csv::Error),
+    Parquet(parquet::errors::ParquetError),
+    Io(std::io::Error),
+    Config(String),
+    /// The database schema doesn't match the version of the binary
+    Schema(String),
+    Task(String),
+}
+
+pub type Result<T> = std::result::Result<T, ScrapeError>;
+
+impl ScrapeError {
+    /// Process exit code for an error that stops a command.
+    /// 1 is kept for a scrape with failed requests and 2 is also used by clap for invalid arguments.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ScrapeError::Config(_) | ScrapeError::SelectorParse { .. } | ScrapeError::InvalidUrl { .. } => 2,
+            ScrapeError::Db(_) | ScrapeError::Schema(_) | ScrapeError::Csv(_) | ScrapeError::Parquet(_) | ScrapeError::Io(_) => 3,
+            ScrapeError::Network(_) | ScrapeError::HttpStatus { .. } | ScrapeError::RobotsDisallowed { .. } | ScrapeError::Task(_) => 4,
+        }
+    }
+}
+
+impl fmt::Display for ScrapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScrapeError::Network(err) => write!(f, "network error: {}", err),
+            ScrapeError::HttpStatus { url, status } => write!(f, "HTTP status {} for '{}'", status, url),
+            ScrapeError::SelectorParse { selector, reason } => write!(f, "invalid CSS selector '{}': {}", selector, reason),
+            ScrapeError::InvalidUrl { url, reason } => write!(f, "invalid url '{}': {}", url, reason),
+            ScrapeError::RobotsDisallowed { url, user_agent } => write!(f, "'{}' is disallowed by robots.txt for user-agent '{}'", url, user_agent),
+            ScrapeError::Db(err) => write!(f, "database error: {}", err),
+            ScrapeError::Csv(err) => write!(f, "csv error: {}", err),
+            ScrapeError::Parquet(err) => write!(f, "parquet error: {}", err),
+            ScrapeError::Io(err) => write!(f, "io error: {}", err),
+            ScrapeError::Config(msg) => write!(f, "config error: {}", msg),
+            ScrapeError::Schema(msg) => write!(f, "database schema error: {}", msg),
+            ScrapeError::Task(msg) => write!(f, "task error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ScrapeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScrapeError::Network(err) => Some(err),
+            ScrapeError::Db(err) => Some(err),
+            ScrapeError::Csv(err) => Some(err),
+            ScrapeError::Parquet(err) => Some(err),
+            ScrapeError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ScrapeError {
+    fn from(err: reqwest::Error) -> ScrapeError {
+        return ScrapeError::Network(err);
+    }
+}
+
+impl From<rusqlite::Error> for ScrapeError {
+    fn from(err: rusqlite::Error) -> ScrapeError {
+        return ScrapeError::Db(err);
+    }
+}
+
+impl From<csv::Error> for ScrapeError {
+    fn from(err: csv::Error) -> ScrapeError {
+        return ScrapeError::Csv(err);
+    }
+}
+
+impl From<parquet::errors::ParquetError> for ScrapeError {
+    fn from(err: parquet::errors::ParquetError) -> ScrapeError {
+        return ScrapeError::Parquet(err);
+    }
+}
+
+impl From<std::io::Error> for ScrapeError {
+    fn from(err: std::io::Error) -> ScrapeError {
+        return ScrapeError::Io(err);
+    }
+}
+
+impl From<tokio::task::JoinError> for ScrapeError {
+    fn from(err: tokio::task::JoinError) -> ScrapeError {
+        return ScrapeError::Task(err.to_string());
+    }
+}
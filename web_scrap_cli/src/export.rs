@@ -0,0 +1,175 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use clap::ArgEnum;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use rusqlite::Connection;
+
+use super::errors::Result;
+use super::query::RecordQuery;
+use super::scrap_utils::SelectorRecord;
+
+/// Records of a parquet row group, the memory used by an export doesn't depend on the size of the table
+const PARQUET_ROW_GROUP_SIZE: usize = 10_000;
+
+const PARQUET_SCHEMA: &str = "
+    message selector_record {
+        REQUIRED INT64 timestamp;
+        REQUIRED BYTE_ARRAY url (UTF8);
+        REQUIRED BYTE_ARRAY selector (UTF8);
+        REQUIRED BYTE_ARRAY content (UTF8);
+        REQUIRED BYTE_ARRAY host (UTF8);
+        REQUIRED BYTE_ARRAY extract (UTF8);
+    }";
+
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat{
+    Csv,
+    Jsonl,
+    Parquet
+}
+
+impl ExportFormat{
+    /// `.jsonl` and `.parquet` files, csv otherwise
+    pub fn from_path(path: &str) -> ExportFormat{
+        return match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("jsonl") => ExportFormat::Jsonl,
+            Some("parquet") => ExportFormat::Parquet,
+            _ => ExportFormat::Csv
+        };
+    }
+}
+
+/// Writes the records one at a time, `finish` writes what is still buffered
+enum RecordWriter{
+    Csv(csv::Writer<Box<dyn Write>>),
+    Jsonl(Box<dyn Write>),
+    Parquet{writer: SerializedFileWriter<Box<dyn Write + Send>>, rows: Vec<SelectorRecord>}
+}
+
+impl RecordWriter{
+    fn new(format: ExportFormat, out: Box<dyn Write + Send>) -> Result<RecordWriter>{
+        return Ok(match format {
+            ExportFormat::Csv => RecordWriter::Csv(csv::Writer::from_writer(out)),
+            ExportFormat::Jsonl => RecordWriter::Jsonl(out),
+            ExportFormat::Parquet => {
+                let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+                let properties = Arc::new(WriterProperties::builder().set_compression(Compression::SNAPPY).build());
+                RecordWriter::Parquet{writer: SerializedFileWriter::new(out, schema, properties)?, rows: Vec::with_capacity(PARQUET_ROW_GROUP_SIZE)}
+            }
+        });
+    }
+
+    fn write(&mut self, record: SelectorRecord) -> Result<()>{
+        match self {
+            RecordWriter::Csv(wtr) => wtr.serialize(record)?,
+            RecordWriter::Jsonl(out) => {
+                serde_json::to_writer(&mut *out, &record).map_err(io::Error::from)?;
+                writeln!(out)?;
+            },
+            RecordWriter::Parquet{writer, rows} => {
+                rows.push(record);
+                if rows.len() == PARQUET_ROW_GROUP_SIZE {
+                    write_row_group(writer, rows)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    fn finish(self) -> Result<()>{
+        match self {
+            RecordWriter::Csv(mut wtr) => wtr.flush()?,
+            RecordWriter::Jsonl(mut out) => out.flush()?,
+            RecordWriter::Parquet{mut writer, mut rows} => {
+                if !rows.is_empty() {
+                    write_row_group(&mut writer, &mut rows)?;
+                }
+                writer.into_inner()?.flush()?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Writes the rows as a row group, in the column order of the schema, and empties them
+fn write_row_group(writer: &mut SerializedFileWriter<Box<dyn Write + Send>>, rows: &mut Vec<SelectorRecord>) -> Result<()>{
+    let text_columns: [fn(&SelectorRecord) -> &str; 5] = [|r| &r.url, |r| &r.selector, |r| &r.content, |r| &r.host, |r| &r.extract];
+    let mut row_group = writer.next_row_group()?;
+    if let Some(mut column) = row_group.next_column()? {
+        let timestamps: Vec<i64> = rows.iter().map(|r| r.timestamp as i64).collect();
+        column.typed::<Int64Type>().write_batch(&timestamps, None, None)?;
+        column.close()?;
+    }
+    for text_column in text_columns {
+        if let Some(mut column) = row_group.next_column()? {
+            let values: Vec<ByteArray> = rows.iter().map(|r| ByteArray::from(text_column(r))).collect();
+            column.typed::<ByteArrayType>().write_batch(&values, None, None)?;
+            column.close()?;
+        }
+    }
+    row_group.close()?;
+    rows.clear();
+    return Ok(());
+}
+
+/// Writes the records of the query to a file, or to stdout for "-", as SQLite reads them.
+/// Returns the number of exported records.
+pub fn export_records(conn: &Connection, table: &str, query: &RecordQuery, format: ExportFormat, output: &str) -> Result<usize>{
+    let out: Box<dyn Write + Send> = match output {
+        "-" => Box::new(io::stdout()),
+        path => Box::new(BufWriter::new(File::create(path)?))
+    };
+    let mut writer = RecordWriter::new(format, out)?;
+    let count = query.for_each(conn, table, |record| writer.write(record))?;
+    writer.finish()?;
+    return Ok(count);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+    use super::super::config::TableOptions;
+    use super::super::db_utils::save_selector_records_to_db;
+    use super::super::migrations::migrate;
+
+    #[test]
+    fn test_export_formats() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let records: Vec<SelectorRecord> = (0..2 * PARQUET_ROW_GROUP_SIZE as u64 + 10)
+            .map(|i| SelectorRecord::new(i, String::from(if i % 2 == 0 { "https://www.cnews.fr/" } else { "https://www.google.fr/" }),
+                                         String::from("h3"), format!("Headline {}", i), String::from("text")))
+            .collect();
+        save_selector_records_to_db(&mut conn, "selector_record", &records, &TableOptions::default(), None).unwrap();
+        let query = RecordQuery{host: Some(String::from("www.cnews.fr")), descending: false, ..RecordQuery::default()};
+        let expected = PARQUET_ROW_GROUP_SIZE + 5;
+
+        let path = std::env::temp_dir().join("web_scrap_cli_test_export.parquet");
+        assert_eq!(ExportFormat::from_path(path.to_str().unwrap()), ExportFormat::Parquet);
+        assert_eq!(export_records(&conn, "selector_record", &query, ExportFormat::Parquet, path.to_str().unwrap()).unwrap(), expected);
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let rows: Vec<parquet::record::Row> = reader.get_row_iter(None).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(rows.len(), expected);
+        assert_eq!(rows[1].get_long(0).unwrap(), 2);
+        assert_eq!(rows[1].get_string(3).unwrap(), "Headline 2");
+        assert_eq!(rows[1].get_string(4).unwrap(), "www.cnews.fr");
+
+        let path = std::env::temp_dir().join("web_scrap_cli_test_export.jsonl");
+        let query = RecordQuery{limit: Some(2), ..query};
+        assert_eq!(export_records(&conn, "selector_record", &query, ExportFormat::Jsonl, path.to_str().unwrap()).unwrap(), 2);
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines[1]["Content"], "Headline 2");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(std::env::temp_dir().join("web_scrap_cli_test_export.parquet")).unwrap();
+    }
+}
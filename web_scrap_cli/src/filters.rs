@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+
+use regex::Regex;
+
+use super::config::FilterSettings;
+use super::errors::{Result, ScrapeError};
+
+/// Number of items dropped by each filter, keyed by the filter description
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilterStats{
+    pub dropped: BTreeMap<String, usize>
+}
+
+impl FilterStats{
+    fn add(&mut self, filter: &str){
+        *self.dropped.entry(filter.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn merge(&mut self, other: &FilterStats){
+        for (filter, count) in &other.dropped {
+            *self.dropped.entry(filter.clone()).or_insert(0) += count;
+        }
+    }
+
+    pub fn total(&self) -> usize{
+        return self.dropped.values().sum();
+    }
+}
+
+/// FilterSettings with compiled regexes, applied to every extracted item of a pair
+#[derive(Debug, Clone)]
+pub struct ContentFilter{
+    enabled: bool,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    normalize_whitespace: bool,
+    whitespace_re: Regex
+}
+
+fn compile_regexes(patterns: &[String]) -> Result<Vec<Regex>>{
+    return patterns.iter()
+                   .map(|p| Regex::new(p).map_err(|e| ScrapeError::Config(format!("invalid filter regex '{}': {}", p, e))))
+                   .collect();
+}
+
+impl ContentFilter{
+    pub fn new(settings: &FilterSettings) -> Result<ContentFilter>{
+        return Ok(ContentFilter{
+            enabled: settings.enabled,
+            include: compile_regexes(&settings.include)?,
+            exclude: compile_regexes(&settings.exclude)?,
+            min_length: settings.min_length,
+            max_length: settings.max_length,
+            normalize_whitespace: settings.normalize_whitespace,
+            whitespace_re: Regex::new(r"\s+").unwrap()
+        });
+    }
+
+    /// Collapses every whitespace sequence into a single space and trims the result
+    pub fn normalize(&self, item: &str) -> String{
+        if !self.normalize_whitespace {
+            return item.to_string();
+        }
+        return self.whitespace_re.replace_all(item.trim(), " ").to_string();
+    }
+
+    /// Returns the item if it passes all filters, otherwise counts it for the first filter that dropped it.
+    /// Lengths are counted in characters.
+    pub fn apply(&self, item: &str, stats: &mut FilterStats) -> Option<String>{
+        if !self.enabled {
+            return Some(item.to_string());
+        }
+        let length = item.chars().count();
+        if let Some(min) = self.min_length.filter(|min| length < *min) {
+            stats.add(&format!("min_length {}", min));
+            return None;
+        }
+        if let Some(max) = self.max_length.filter(|max| length > *max) {
+            stats.add(&format!("max_length {}", max));
+            return None;
+        }
+        if !self.include.is_empty() && !self.include.iter().any(|re| re.is_match(item)) {
+            stats.add("include");
+            return None;
+        }
+        if let Some(re) = self.exclude.iter().find(|re| re.is_match(item)) {
+            stats.add(&format!("exclude '{}'", re.as_str()));
+            return None;
+        }
+        return Some(item.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_filter() {
+        let settings = FilterSettings{include: vec![String::from(r"^\p{Lu}")],
+                                      exclude: vec![String::from("^Publicité")],
+                                      max_length: Some(22),
+                                      ..FilterSettings::default()};
+        let filter = ContentFilter::new(&settings).unwrap();
+        let mut stats = FilterStats::default();
+
+        let item = filter.normalize("  Élections   européennes \n");
+        assert_eq!(filter.apply(&item, &mut stats), Some(String::from("Élections européennes")));
+        assert_eq!(filter.apply("abc", &mut stats), None);
+        assert_eq!(filter.apply("lowercase headline", &mut stats), None);
+        assert_eq!(filter.apply("Publicité : achetez", &mut stats), None);
+        assert_eq!(filter.apply("A very very long headline", &mut stats), None);
+
+        assert_eq!(stats.total(), 4);
+        assert_eq!(stats.dropped["min_length 4"], 1);
+        assert_eq!(stats.dropped["include"], 1);
+        assert_eq!(stats.dropped["exclude '^Publicité'"], 1);
+        assert_eq!(stats.dropped["max_length 22"], 1);
+    }
+
+    #[test]
+    fn test_disabled_filter_keeps_everything() {
+        let settings = FilterSettings{enabled: false, ..FilterSettings::default()};
+        let filter = ContentFilter::new(&settings).unwrap();
+        let mut stats = FilterStats::default();
+        assert_eq!(filter.apply("", &mut stats), Some(String::new()));
+        assert_eq!(stats.total(), 0);
+    }
+}
@@ -0,0 +1,191 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rusqlite::Connection;
+
+use super::db_utils::import_selector_records;
+use super::errors::{Result, ScrapeError};
+use super::file_utils::get_csv_reader;
+use super::scrap_utils::{get_host_from_url, Extraction, SelectorRecord, SelectorSpec};
+
+/// Columns of a selector records csv, `Host` and `Extract` are optional as older files don't have them all
+const REQUIRED_COLUMNS: [&str; 4] = ["Timestamp", "Url", "Selector", "Content"];
+
+/// Outcome of the import of a csv file
+#[derive(Debug, Default, PartialEq)]
+pub struct FileImport{
+    pub imported: usize,
+    pub duplicates: usize,
+    /// Line and reason of each row that isn't a valid record
+    pub invalid: Vec<(u64, String)>
+}
+
+/// The csv files of the paths, a directory gives the `.csv` files of its tree sorted by path,
+/// so that the files of a csv name template with sub directories are found
+pub fn csv_files(paths: &[String]) -> Result<Vec<PathBuf>>{
+    let mut files = Vec::new();
+    for path in paths.iter().map(Path::new) {
+        if path.is_dir() {
+            let mut dir_files = Vec::new();
+            find_csv_files(path, &mut dir_files)?;
+            dir_files.sort();
+            files.extend(dir_files);
+        } else if path.is_file() {
+            files.push(path.to_path_buf());
+        } else {
+            return Err(ScrapeError::Io(io::Error::new(io::ErrorKind::NotFound, format!("'{}' not found", path.display()))));
+        }
+    }
+    return Ok(files);
+}
+
+fn find_csv_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()>{
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_csv_files(&path, files)?;
+        } else if path.is_file() && path.extension().map(|e| e.eq_ignore_ascii_case("csv")).unwrap_or(false) {
+            files.push(path);
+        }
+    }
+    return Ok(());
+}
+
+/// Inserts the valid rows of a selector records csv that aren't in the table yet
+pub fn import_csv_file(conn: &mut Connection, table: &str, path: &Path, delimiter: u8, quote: u8) -> Result<FileImport>{
+    let mut reader = get_csv_reader(path.display().to_string(), delimiter, quote)?;
+    let headers = reader.headers()?.clone();
+    if let Some(missing) = REQUIRED_COLUMNS.iter().find(|column| !headers.iter().any(|h| h == **column)) {
+        return Err(ScrapeError::Io(io::Error::new(io::ErrorKind::InvalidData,
+            format!("not a csv of selector records, no '{}' column (is the delimiter right ?)", missing))));
+    }
+
+    let mut report = FileImport::default();
+    let mut records = Vec::new();
+    for row in reader.records() {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => match e.kind() {
+                csv::ErrorKind::UnequalLengths{pos, expected_len, len} => {
+                    let line = pos.as_ref().map(|p| p.line()).unwrap_or_default();
+                    report.invalid.push((line, format!("{} fields instead of {}", len, expected_len)));
+                    continue;
+                },
+                _ => return Err(e.into())
+            }
+        };
+        let line = row.position().map(|p| p.line()).unwrap_or_default();
+        let record = row.deserialize::<SelectorRecord>(Some(&headers))
+            .map_err(|e| match e.kind() {
+                csv::ErrorKind::Deserialize{err, ..} => {
+                    let column = err.field().and_then(|i| headers.get(i as usize)).unwrap_or("?");
+                    format!("{} : {}", column, err.kind())
+                },
+                _ => e.to_string()
+            })
+            .and_then(check_record);
+        match record {
+            Ok(record) => records.push(record),
+            Err(reason) => report.invalid.push((line, reason))
+        }
+    }
+    report.imported = import_selector_records(conn, table, &records)?;
+    report.duplicates = records.len() - report.imported;
+    return Ok(report);
+}
+
+/// A record read from a csv must have what a scraped one has, a missing host is taken from the url
+/// and a missing extract from the selector. Older files kept the extraction in the extract column only,
+/// it is added to their selector as a scrape does now.
+fn check_record(mut record: SelectorRecord) -> std::result::Result<SelectorRecord, String>{
+    let host = get_host_from_url(&record.url).map_err(|e| e.to_string())?;
+    if record.selector.trim().is_empty() {
+        return Err(String::from("Selector : empty"));
+    }
+    let mut spec = SelectorSpec::parse(&record.selector).map_err(|e| format!("Selector : {}", e))?;
+    if !record.extract.is_empty() {
+        let extraction = Extraction::from_name(&record.extract).ok_or_else(|| format!("Extract : unknown extraction '{}'", record.extract))?;
+        if !spec.has_extraction() {
+            spec.extraction = extraction;
+        } else if spec.extraction != extraction {
+            return Err(format!("Extract : '{}' isn't the extraction of '{}'", record.extract, record.selector));
+        }
+    }
+    record.selector = spec.to_string();
+    record.extract = spec.extraction.to_string();
+    if record.content.is_empty() {
+        return Err(String::from("Content : empty"));
+    }
+    if record.host.is_empty() {
+        record.host = host;
+    } else if record.host != host {
+        return Err(format!("Host : '{}' isn't the host of '{}'", record.host, record.url));
+    }
+    return Ok(record);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::migrations::migrate;
+
+    #[test]
+    fn test_import_csv_file() {
+        let dir = std::env::temp_dir().join("web_scrap_cli_test_import");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // an older file without the Extract column, with a duplicate row and invalid ones
+        fs::write(dir.join("records_www.cnews.fr_1_0.csv"), "Timestamp;Url;Selector;Content;Host\n\
+                                                             100;https://www.cnews.fr/;h3;Headline 1;www.cnews.fr\n\
+                                                             100;https://www.cnews.fr/;h3;Headline 1;www.cnews.fr\n\
+                                                             yesterday;https://www.cnews.fr/;h3;Headline 2;www.cnews.fr\n\
+                                                             100;not an url;h3;Headline 3;www.cnews.fr\n\
+                                                             100;https://www.cnews.fr/;h3;;www.cnews.fr\n\
+                                                             100;https://www.cnews.fr/;h3\n\
+                                                             200;https://www.cnews.fr/;a@href;https://www.cnews.fr/sport;www.cnews.fr\n").unwrap();
+        // a file written when the selector didn't keep its extraction
+        fs::write(dir.join("records_www.cnews.fr_2_0.csv"), "Timestamp;Url;Selector;Content;Host;Extract\n\
+                                                             400;https://www.cnews.fr/;div;<b>Sport</b>;www.cnews.fr;html\n\
+                                                             400;https://www.cnews.fr/;a@href;https://www.cnews.fr/;www.cnews.fr;html\n").unwrap();
+        fs::write(dir.join("other.csv"), "a;b\n1;2\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not a csv").unwrap();
+        // a file of a csv name template with sub directories, with a custom quote
+        fs::create_dir_all(dir.join("2024").join("05")).unwrap();
+        fs::write(dir.join("2024").join("05").join("www.cnews.fr.csv"), "Timestamp;Url;Selector;Content\n\
+                                                                          300;https://www.cnews.fr/;h3;'Sport ; Football'\n").unwrap();
+        let files = csv_files(&[dir.to_str().unwrap().to_string()]).unwrap();
+        assert_eq!(files, vec![dir.join("2024").join("05").join("www.cnews.fr.csv"), dir.join("other.csv"), dir.join("records_www.cnews.fr_1_0.csv"),
+                               dir.join("records_www.cnews.fr_2_0.csv")]);
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let report = import_csv_file(&mut conn, "selector_record", &files[2], b';', b'"').unwrap();
+        assert_eq!((report.imported, report.duplicates), (2, 1));
+        let invalid_lines: Vec<u64> = report.invalid.iter().map(|(line, _)| *line).collect();
+        assert_eq!(invalid_lines, vec![4, 5, 6, 7]);
+        assert!(report.invalid[0].1.starts_with("Timestamp"), "{:?}", report.invalid);
+
+        // the records already imported are skipped
+        let report = import_csv_file(&mut conn, "selector_record", &files[2], b';', b'"').unwrap();
+        assert_eq!((report.imported, report.duplicates), (0, 3));
+        let selectors = |conn: &Connection| -> Vec<(String, String)> {
+            conn.prepare("SELECT selector, extract FROM selector_record ORDER BY id").unwrap()
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().map(|r| r.unwrap()).collect()
+        };
+        assert_eq!(selectors(&conn), vec![(String::from("h3"), String::from("text")), (String::from("a@href"), String::from("attr:href"))]);
+
+        let report = import_csv_file(&mut conn, "selector_record", &files[3], b';', b'"').unwrap();
+        assert_eq!(report.imported, 1);
+        assert!(report.invalid[0].1.starts_with("Extract"), "{:?}", report.invalid);
+        assert_eq!(selectors(&conn)[2], (String::from("div::html"), String::from("html")));
+
+        let report = import_csv_file(&mut conn, "selector_record", &files[0], b';', b'\'').unwrap();
+        assert_eq!((report.imported, report.invalid.len()), (1, 0));
+        let content: String = conn.query_row("SELECT content FROM selector_record WHERE timestamp = 300", [], |row| row.get(0)).unwrap();
+        assert_eq!(content, "Sport ; Football");
+
+        assert!(import_csv_file(&mut conn, "selector_record", &files[1], b';', b'"').is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
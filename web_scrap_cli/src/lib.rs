@@ -0,0 +1,272 @@
+#![allow(clippy::needless_return)]
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use reqwest::Client;
+use scraper::Html;
+use futures::future::join_all;
+use tokio::task;
+use log::*;
+
+pub mod errors;
+use errors::{Result, ScrapeError};
+
+mod config_file;
+
+pub mod config;
+pub use config::Config;
+use config::{FetchSettings, UrlSelectorPair};
+
+pub mod commands;
+
+mod query;
+
+mod search;
+
+mod import;
+
+mod export;
+
+mod stats;
+
+mod retention;
+
+pub mod scrap_utils;
+use scrap_utils::*;
+
+mod file_utils;
+use file_utils::*;
+
+mod db_utils;
+pub use db_utils::SaveOutcome;
+
+mod migrations;
+
+mod politeness;
+use politeness::Politeness;
+
+mod filters;
+pub use filters::FilterStats;
+use filters::ContentFilter;
+
+mod robots;
+use robots::RobotsCache;
+
+pub mod sinks;
+use sinks::{sinks_from_config, RecordBatch, RecordSink};
+
+mod writer;
+use writer::{flush_ticker, Writer, WriterMessage};
+
+/// `UrlSelectorPair`s that couldn't be scraped or saved, kept for the end of run report.
+/// A page failure is shared by all the pairs of the url.
+#[derive(Debug)]
+pub struct RequestFailure {
+    pub url_selectors: Vec<UrlSelectorPair>,
+    pub error: ScrapeError
+}
+
+impl RequestFailure {
+    fn describe(&self) -> String {
+        let selectors: Vec<&str> = self.url_selectors.iter().map(|p| p.selector.as_str()).collect();
+        let url = self.url_selectors.first().map(|p| p.url.as_str()).unwrap_or_default();
+        return format!("[{}] {}", selectors.join(", "), url);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    pub saved: SaveOutcome,
+    pub filter_stats: FilterStats,
+    pub failures: Vec<RequestFailure>
+}
+
+impl RunSummary {
+    pub fn print(&self){
+        eprintln!("--------------\nRun Summary:");
+        eprintln!("{} records saved, {} new", self.saved.new + self.saved.seen_again, self.saved.new);
+        if self.saved.seen_again > 0 {
+            eprintln!("{} records already seen, their last_seen and seen_count were updated", self.saved.seen_again);
+        }
+        if self.filter_stats.total() > 0 {
+            eprintln!("{} items dropped by filters :", self.filter_stats.total());
+            for (filter, count) in &self.filter_stats.dropped {
+                eprintln!("  - {} : {}", filter, count);
+            }
+        }
+        let (skipped, failed): (Vec<&RequestFailure>, Vec<&RequestFailure>) = self.failures.iter()
+            .partition(|f| matches!(f.error, ScrapeError::RobotsDisallowed{..}));
+        if !skipped.is_empty() {
+            eprintln!("{} request(s) skipped because of robots.txt :", skipped.len());
+            for failure in skipped {
+                eprintln!("  - {}", failure.describe());
+            }
+        }
+        if failed.is_empty() {
+            eprintln!("All requests succeeded");
+        } else {
+            eprintln!("{} failed request(s) :", failed.len());
+            for failure in failed {
+                eprintln!("  - {} => {}", failure.describe(), failure.error);
+            }
+        }
+        eprintln!("--------------");
+    }
+
+    /// Failures other than the robots.txt skips
+    pub fn failed_count(&self) -> usize{
+        return self.failures.iter().filter(|f| !matches!(f.error, ScrapeError::RobotsDisallowed{..})).count();
+    }
+
+    fn add_failure(&mut self, url_selectors: Vec<UrlSelectorPair>, error: ScrapeError){
+        let failure = RequestFailure{url_selectors, error};
+        warn!("Failed to handle {} : {}", failure.describe(), failure.error);
+        self.failures.push(failure);
+    }
+}
+
+/// Scrapes with the outputs of the config, they are opened before any request so that a bad database fails early
+pub async fn run(config: Config) -> Result<RunSummary>{    
+    let sinks = sinks_from_config(&config)?;
+    return run_with_sinks(config, sinks).await;
+}
+
+pub async fn run_with_sinks(config: Config, mut sinks: Vec<Box<dyn RecordSink>>) -> Result<RunSummary>{    
+    
+    let started_at = get_timestamp_now();
+    let config_hash = config.config_hash();
+    let mut run_id = None;
+    for sink in sinks.iter_mut() {
+        run_id = run_id.or(sink.start_run(started_at, &config_hash)?);
+    }
+    // without a sink keeping the runs the start time identifies the run
+    let run_id = run_id.unwrap_or(started_at as i64);
+
+    // the records are written by a thread as the requests complete, in batches
+    let config = Arc::new(config);
+    let writer = Writer::spawn(config.clone(), sinks, run_id)?;
+    let ticker = task::spawn(flush_ticker(writer.sender.clone(), config.writer.flush_interval));
+
+    // connect timeout is a client setting, one client is shared by all pairs with the same value
+    let mut clients: HashMap<Duration, Client> = HashMap::new();
+    let politeness = Arc::new(Politeness::new(&config.politeness));
+    let robots = Arc::new(RobotsCache::new(&config.robots));
+
+    // separate threads for parrallel execution, one per url
+    let url_selector_groups = config.url_selector_groups();
+    let mut futures = vec![];    
+    let mut index = 0;
+    for url_selectors in &url_selector_groups {
+        // the page is fetched once with the most permissive settings of its pairs
+        let fetch_settings = config.fetch_settings_for_group(url_selectors);
+        let client = match clients.get(&fetch_settings.connect_timeout) {
+            Some(c) => c.clone(),
+            None => {
+                let c = Client::builder().cookie_store(true)
+                                         .user_agent(&config.robots.user_agent)
+                                         .connect_timeout(fetch_settings.connect_timeout).build()?;
+                clients.insert(fetch_settings.connect_timeout, c.clone());
+                c
+            }
+        };
+        let filters = url_selectors.iter()
+                                   .map(|p| ContentFilter::new(&config.filter_settings_for(p)))
+                                   .collect::<Result<Vec<ContentFilter>>>()?;
+        let sender = writer.sender.clone();
+        let (url_selectors, page_index, politeness, robots) = (url_selectors.clone(), index, politeness.clone(), robots.clone());
+        index += url_selectors.len();
+        let fut = task::spawn(async move {
+            // the writer only stops early on an error that fails the run, the requests still waiting or running are dropped
+            let page = tokio::select! {
+                page = handle_request(client, url_selectors.clone(), fetch_settings, filters, politeness, robots) => page,
+                _ = sender.closed() => return
+            };
+            // waits while the writer queue is full, a writer stopping meanwhile returns its error
+            let _ = sender.send(WriterMessage::Page{index: page_index, url_selectors, page}).await;
+        });
+        futures.push(fut);               
+    }
+
+    let results = join_all(futures).await;
+    for (result, url_selectors) in results.into_iter().zip(url_selector_groups){
+        if let Err(e) = result {
+            let _ = writer.sender.send(WriterMessage::Failed{url_selectors, error: ScrapeError::from(e)}).await;
+        }
+    }
+    ticker.abort();
+    let _ = ticker.await;
+    return writer.finish().await;
+}
+
+/// Fetches the url shared by the given pairs once, then applies each selector on the parsed page.
+/// The outer error is for the page, the inner ones for each selector, in the pairs order.
+/// `filters` has one ContentFilter per pair. The fetch metadata is returned whenever a request was sent.
+pub async fn handle_request(client: Client, url_selectors: Vec<UrlSelectorPair>, fetch_settings: FetchSettings, filters: Vec<ContentFilter>,
+                            politeness: Arc<Politeness>, robots: Arc<RobotsCache>) -> PageResult {
+    let mut fetch_info = None;
+    let result = scrape_page(&client, &url_selectors, &fetch_settings, &filters, &politeness, &robots, &mut fetch_info).await;
+    return (fetch_info, result);
+}
+
+pub type PageResult = (Option<FetchInfo>, Result<(Vec<Result<PairRecords>>, FilterStats)>);
+
+async fn scrape_page(client: &Client, url_selectors: &[UrlSelectorPair], fetch_settings: &FetchSettings, filters: &[ContentFilter],
+                     politeness: &Politeness, robots: &RobotsCache, fetch_info: &mut Option<FetchInfo>) -> Result<(Vec<Result<PairRecords>>, FilterStats)> {
+            
+    let start = Instant::now(); 
+    let url = &url_selectors[0].url;
+    // fail early on a bad url or selectors, before any network access
+    let host = get_host_from_url(url)?;
+    if url_selectors.iter().all(|p| SelectorSpec::parse(&p.selector).is_err()) {
+        let selector_results = url_selectors.iter().map(|p| SelectorSpec::parse(&p.selector).map(|_| PairRecords::Selector(Vec::new()))).collect();
+        return Ok((selector_results, FilterStats::default()));
+    }
+    robots.check(client, url, fetch_settings, politeness).await?;
+    let permit = politeness.acquire(&host).await;
+    eprintln!("Sending request ...");
+    let (info, content) = fetch_page(client, url, fetch_settings).await;
+    *fetch_info = Some(info);
+    drop(permit);
+    let content = content?;
+    eprintln!("Received request content !");
+    let _links = extract_all_links(&content).await;    
+
+    let document = Html::parse_document(&content);
+    let mut filter_stats = FilterStats::default();
+    let selector_results = url_selectors.iter().zip(filters)
+        .map(|(p, filter)| if p.is_structured() {
+            extract_structured_records(&document, url, &p.selector, &p.fields).map(PairRecords::Structured)
+        } else {
+            extract_selector_records(&document, url, &p.selector, filter, &mut filter_stats).map(PairRecords::Selector)
+        })
+        .collect();
+    eprintln!("-------------------\nDuration to handle request is: {:?}\n", start.elapsed());
+      
+    return Ok((selector_results, filter_stats));
+}
+
+
+/// Writes the records of a pair to every sink, a failing sink doesn't prevent the others from writing.
+/// The reported counts are the ones of the sink that found the most records already seen.
+pub fn handle_records(sinks: &mut [Box<dyn RecordSink>], batch: &RecordBatch) -> Result<SaveOutcome> {
+    let start = Instant::now();         
+    let mut outcome = SaveOutcome::all_new(batch.records.len());
+    let mut first_error = None;
+    for sink in sinks.iter_mut() {
+        match sink.write(batch) {
+            Ok(sink_outcome) if sink_outcome.seen_again > outcome.seen_again => outcome = sink_outcome,
+            Ok(_) => {},
+            Err(e) => {
+                warn!("Failed to write to {} : {}", sink.name(), e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+      
+    eprintln!("__________________\nDuration to handle records is: {:?}\n", start.elapsed());  
+    return match first_error {
+        Some(e) => Err(e),
+        None => Ok(outcome)
+    };
+}
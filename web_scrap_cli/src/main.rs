@@ -1,32 +1,17 @@
+#![allow(clippy::needless_return)]
+
 use std::time::Instant;
 use std::io::Write;
-use reqwest::Client;
+use std::process::ExitCode;
 use clap::Parser;
-use rusqlite::Connection;
-//use futures::prelude::*;
-use futures::future::join_all;
-use tokio::task;
 use log::*;
 
-mod config;
-use config::{Args, Config, UrlSelectorPair};
-
-mod scrap_utils;
-use scrap_utils::*;
-
-mod file_utils;
-use file_utils::*;
-
-mod db_utils;
-use db_utils::*;
-
-
-const CSV_NAME_PREFIX: &str = "records_";
-
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
-
+use web_scrap_cli::commands;
+use web_scrap_cli::config::{Args, Command, DbCommand, ScrapeArgs};
+use web_scrap_cli::errors::Result;
+use web_scrap_cli::run;
 
-fn main() -> Result<()>{    
+fn main() -> ExitCode{
     
     let start = std::time::Instant::now();
 
@@ -36,99 +21,49 @@ fn main() -> Result<()>{
         writeln!(buf, "{:.03} [{}] - {}", t, rec.level(), rec.args())
     }).init();
 
-    // use cli args with possible yaml config use   
+    // without subcommand, the top level arguments are the scrape ones
     let args = Args::parse();
-    let config = args.build_config();
-    config.print_info();    
-    println!("-------------------\nDuration to build config is: {:?}\n", start.elapsed());
-      
-    // Start asynced work
-    let rt = tokio::runtime::Runtime::new().unwrap();    
-    match rt.block_on(run(config)) {
-        Ok(_) => info!("Done"),
-        Err(e) => error!("Run() : An error ocurred: {}", e),
+    let result = match args.command.unwrap_or(Command::Scrape(args.scrape)) {
+        Command::Scrape(scrape_args) => scrape(&scrape_args, start),
+        Command::Stats(stats_args) => commands::stats(&stats_args).map(|_| ExitCode::SUCCESS),
+        Command::Query(query_args) => commands::query(&query_args).map(|_| ExitCode::SUCCESS),
+        Command::Search(search_args) => commands::search(&search_args).map(|_| ExitCode::SUCCESS),
+        Command::Export(export_args) => commands::export(&export_args).map(|_| ExitCode::SUCCESS),
+        Command::Import(import_args) => commands::import(&import_args).map(|_| ExitCode::SUCCESS),
+        Command::Prune(prune_args) => commands::prune(&prune_args).map(|_| ExitCode::SUCCESS),
+        Command::Validate(scrape_args) => commands::validate(&scrape_args).map(|_| ExitCode::SUCCESS),
+        Command::Db(DbCommand::Migrate(db_args)) => commands::db_migrate(&db_args).map(|_| ExitCode::SUCCESS),
+        Command::Db(DbCommand::Status(db_args)) => commands::db_status(&db_args).map(|_| ExitCode::SUCCESS),
+    };
+    return match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(e.exit_code())
+        }
     };
-
-    println!("\n_________\nTotal duration = {} sec.\nTerminating program...", start.elapsed().as_secs_f32());
-    return Ok(())
 }
 
-pub async fn run(config: Config) -> Result<()>{    
-    
-    let client = Client::builder().cookie_store(true).build()?;
-
-    // separate threads for parrallel execution
-    let mut futures = vec![];    
-    for url_selector in &config.url_selectors {
-        let fut = task::spawn(handle_request(client.clone(), url_selector.clone())
-        );
-        futures.push(fut);               
-    }
-
-    // Retrieve all selector items in results
-    let results = join_all(futures).await;
-
-    // Setup db connection if required
-    let mut conn: Option<Connection> = match &config.db_path {
-        Some(p) => {
-            let new_conn: Connection = Connection::open(&p).unwrap();
-            new_conn.execute_batch("PRAGMA journal_mode = OFF;
-                                    PRAGMA synchronous = 0;
-                                    PRAGMA cache_size = 1000000;
-                                    PRAGMA locking_mode = EXCLUSIVE;
-                                    PRAGMA temp_store = MEMORY;",
-                                    ).expect("db connection PRAGMA error !");
-            Some(new_conn)
-        },
-        _ => None
-    }; 
-
-    let mut i: usize = 0;
-    for result in results{
-        let records = result?.unwrap();
-        if records.len() > 0 {
-            let req_id = format!("{}_{}", get_timestamp_now(), i);
-            handle_records(&records, &mut conn, config.table.clone(), config.save_to_csv.clone(), req_id).await.unwrap();
-        }        
-        i += 1;
-    }
+/// Exit code of a scrape that completed with some failed requests, pages skipped because of robots.txt don't count
+const EXIT_REQUEST_FAILURES: u8 = 1;
 
-    if config.print_db_stats {
-        if let Some(valid_conn) = conn {
-            print_db_stats(&valid_conn).expect("Couldn't get database stats");
-        }
+fn scrape(args: &ScrapeArgs, start: Instant) -> Result<ExitCode>{
+    let config = args.build_config()?;
+    for warning in &config.warnings {
+        eprintln!("Warning: {}", warning);
     }
-         
-    return Ok(());
-}
-
-pub async fn handle_request(client: Client, url_selector: UrlSelectorPair) -> Result<Vec<SelectorRecord>> {
-            
-    println!("Sending request ...");
-    let start = Instant::now(); 
-    let content = get_body_from(&client, &url_selector.url).await;  
-    println!("Received request content !");
-    let _links = extract_all_links(&content).await;    
-    let records = extract_selector_records(&content, &url_selector.url, &url_selector.selector).await.unwrap();
-    println!("-------------------\nDuration to handle request is: {:?}\n", start.elapsed());
+    config.print_info();    
+    eprintln!("-------------------\nDuration to build config is: {:?}\n", start.elapsed());
       
-    return Ok(records);
+    // Start asynced work
+    let rt = tokio::runtime::Runtime::new()?;    
+    let summary = rt.block_on(run(config))?;
+    summary.print();
+    info!("Done");
+
+    eprintln!("\n_________\nTotal duration = {} sec.\nTerminating program...", start.elapsed().as_secs_f32());
+    if summary.failed_count() > 0 {
+        return Ok(ExitCode::from(EXIT_REQUEST_FAILURES));
+    }
+    return Ok(ExitCode::SUCCESS);
 }
-
-
-pub async fn handle_records(records: &Vec<SelectorRecord>, conn: &mut Option<Connection>, table: String, save_to_csv: bool, req_id: String) -> Result<()> {
-    let start = Instant::now();         
-    
-    if save_to_csv {        
-        save_records_to_csv(&records, 
-            std::env::current_dir()?.join(format!("{}{}_{}.csv", CSV_NAME_PREFIX, &records[0].host, req_id))
-        ).await.unwrap();
-    };
-    match conn {
-        Some(valid_conn) => save_selector_records_to_db(valid_conn, &table, &records).unwrap(),        
-        _ => {}           
-    };        
-      
-    println!("__________________\nDuration to handle records is: {:?}\n", start.elapsed());  
-    return Ok(());
-}
\ No newline at end of file
@@ -0,0 +1,341 @@
+use rusqlite::{params, Connection};
+
+use super::db_utils::{get_col_names, get_db_table_names, quote_ident};
+use super::errors::{Result, ScrapeError};
+use super::file_utils::get_timestamp_now;
+use super::scrap_utils::{get_host_from_url, Extraction, SelectorRecord, SelectorSpec};
+
+/// Applied migrations, one row per version
+pub const SCHEMA_TABLE: &str = "schema_migrations";
+/// Record tables of the database and their kind, the table names are chosen by the config
+/// so a migration of the record tables needs this list to find them
+pub const RECORD_TABLES: &str = "record_tables";
+/// One row per scrape
+pub const RUNS_TABLE: &str = "runs";
+/// One row per page fetched by a run, the records of the page link to it with their `fetch_id`
+pub const FETCHES_TABLE: &str = "fetches";
+/// Tables of the scraper itself, not record tables
+pub const INTERNAL_TABLES: [&str; 4] = [SCHEMA_TABLE, RECORD_TABLES, RUNS_TABLE, FETCHES_TABLE];
+
+pub const SELECTOR_KIND: &str = "selector";
+pub const STRUCTURED_KIND: &str = "structured";
+
+/// A schema change, applied once in its own transaction.
+/// The tables created by the current code already have the latest schema,
+/// the migrations only upgrade the tables of an older database.
+pub struct Migration{
+    pub version: u32,
+    pub description: &'static str,
+    apply: fn(&Connection) -> Result<()>
+}
+
+/// Ordered by version, a released migration must never change, add a new one instead
+pub const MIGRATIONS: &[Migration] = &[
+    Migration{version: 1, description: "Register the existing record tables", apply: register_existing_tables},
+    Migration{version: 2, description: "Add the host column to the selector record tables", apply: add_host_column},
+    Migration{version: 3, description: "Add the extract column to the selector record tables", apply: add_extract_column},
+    Migration{version: 4, description: "Add the dedup columns and content hash index to the selector record tables", apply: add_dedup_columns},
+    Migration{version: 5, description: "Add the runs and fetches tables, the record tables link to their fetch", apply: add_runs_and_fetches},
+    Migration{version: 6, description: "Keep the extraction in the selector of the attribute and html records", apply: add_extraction_to_selectors},
+];
+
+/// Schema version of this binary
+pub fn latest_version() -> u32{
+    return MIGRATIONS.last().map(|m| m.version).unwrap_or(0);
+}
+
+/// 0 for a database created before the migrations or a new one
+pub fn schema_version(conn: &Connection) -> Result<u32>{
+    if !table_exists(conn, SCHEMA_TABLE)? {
+        return Ok(0);
+    }
+    let version: Option<u32> = conn.query_row(&format!("SELECT MAX(version) FROM {}", SCHEMA_TABLE), [], |row| row.get(0))?;
+    return Ok(version.unwrap_or(0));
+}
+
+/// Versions and unix timestamps of the applied migrations
+pub fn applied_migrations(conn: &Connection) -> Result<Vec<(u32, u64)>>{
+    if !table_exists(conn, SCHEMA_TABLE)? {
+        return Ok(Vec::new());
+    }
+    let mut stmt = conn.prepare(&format!("SELECT version, applied_at FROM {} ORDER BY version", SCHEMA_TABLE))?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    let mut applied = Vec::new();
+    for row in rows {
+        applied.push(row?);
+    }
+    return Ok(applied);
+}
+
+fn check_not_newer(version: u32) -> Result<()>{
+    if version > latest_version() {
+        return Err(ScrapeError::Schema(format!("database schema version {} is newer than the version {} of this binary, upgrade web_scrap_cli",
+                                               version, latest_version())));
+    }
+    return Ok(());
+}
+
+/// Check for the read-only commands, they can't migrate the database
+pub fn check_schema(conn: &Connection) -> Result<()>{
+    let version = schema_version(conn)?;
+    check_not_newer(version)?;
+    if version < latest_version() {
+        return Err(ScrapeError::Schema(format!("database schema version {} is older than the version {} of this binary, run `web_scrap_cli db migrate`",
+                                               version, latest_version())));
+    }
+    return Ok(());
+}
+
+/// Applies the pending migrations in order and returns them, a database newer than the binary is refused
+pub fn migrate(conn: &mut Connection) -> Result<Vec<&'static Migration>>{
+    let version = schema_version(conn)?;
+    check_not_newer(version)?;
+    conn.execute(&format!("CREATE TABLE IF NOT EXISTS {} (
+                           version         INTEGER PRIMARY KEY,
+                           description     TEXT NOT NULL,
+                           applied_at      INTEGER NOT NULL
+                           )", SCHEMA_TABLE), [])?;
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        let transaction = conn.transaction()?;
+        (migration.apply)(&transaction)?;
+        transaction.execute(&format!("INSERT INTO {} (version, description, applied_at) VALUES (?1, ?2, ?3)", SCHEMA_TABLE),
+                            params![migration.version, migration.description, get_timestamp_now()])?;
+        transaction.commit()?;
+        applied.push(migration);
+    }
+    return Ok(applied);
+}
+
+/// Names and kinds of the registered record tables, the dropped ones are left out
+pub fn record_tables(conn: &Connection) -> Result<Vec<(String, String)>>{
+    if !table_exists(conn, RECORD_TABLES)? {
+        return Ok(Vec::new());
+    }
+    let mut stmt = conn.prepare(&format!("SELECT name, kind FROM {} WHERE name IN (SELECT name FROM sqlite_schema WHERE type = 'table') ORDER BY name",
+                                         RECORD_TABLES))?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    let mut tables = Vec::new();
+    for row in rows {
+        tables.push(row?);
+    }
+    return Ok(tables);
+}
+
+pub fn register_record_table(conn: &Connection, table_name: &str, kind: &str) -> Result<()>{
+    conn.execute(&format!("INSERT OR IGNORE INTO {} (name, kind, created_at) VALUES (?1, ?2, ?3)", RECORD_TABLES),
+                 params![table_name, kind, get_timestamp_now()])?;
+    return Ok(());
+}
+
+fn table_exists(conn: &Connection, table_name: &str) -> Result<bool>{
+    let count: u32 = conn.query_row("SELECT COUNT(*) FROM sqlite_schema WHERE type = 'table' AND name = ?1", [table_name], |row| row.get(0))?;
+    return Ok(count > 0);
+}
+
+fn record_tables_of_kind(conn: &Connection, kind: &str) -> Result<Vec<String>>{
+    return Ok(record_tables(conn)?.into_iter().filter(|(_, k)| k == kind).map(|(name, _)| name).collect());
+}
+
+fn add_missing_columns(conn: &Connection, table_name: &str, columns: &[(&str, &str)]) -> Result<Vec<String>>{
+    let existing_columns = get_col_names(conn, table_name)?;
+    let mut added = Vec::new();
+    for (column, column_type) in columns.iter().filter(|(c, _)| !existing_columns.iter().any(|e| e == c)) {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", quote_ident(table_name), column, column_type), [])?;
+        added.push(column.to_string());
+    }
+    return Ok(added);
+}
+
+/// Selector tables are told apart by their content column, the structured ones have a column per field
+fn register_existing_tables(conn: &Connection) -> Result<()>{
+    conn.execute(&format!("CREATE TABLE IF NOT EXISTS {} (
+                           name            TEXT PRIMARY KEY,
+                           kind            TEXT NOT NULL,
+                           created_at      INTEGER NOT NULL
+                           )", RECORD_TABLES), [])?;
+    for table in get_db_table_names(conn)? {
+        let columns = get_col_names(conn, &table)?;
+        let has = |name: &str| columns.iter().any(|c| c == name);
+        if !has("url") || !has("selector") || !has("timestamp") {
+            continue;
+        }
+        register_record_table(conn, &table, if has("content") { SELECTOR_KIND } else { STRUCTURED_KIND })?;
+    }
+    return Ok(());
+}
+
+/// The host of the existing rows is taken from their url
+fn add_host_column(conn: &Connection) -> Result<()>{
+    for table in record_tables_of_kind(conn, SELECTOR_KIND)? {
+        if add_missing_columns(conn, &table, &[("host", "TEXT NOT NULL DEFAULT ''")])?.is_empty() {
+            continue;
+        }
+        let mut select = conn.prepare(&format!("SELECT id, url FROM {}", quote_ident(&table)))?;
+        let rows = select.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+        let mut update = conn.prepare(&format!("UPDATE {} SET host = ?1 WHERE id = ?2", quote_ident(&table)))?;
+        for row in rows {
+            let (id, url) = row?;
+            update.execute(params![get_host_from_url(&url).unwrap_or_default(), id])?;
+        }
+    }
+    return Ok(());
+}
+
+fn add_extract_column(conn: &Connection) -> Result<()>{
+    for table in record_tables_of_kind(conn, SELECTOR_KIND)? {
+        add_missing_columns(conn, &table, &[("extract", "TEXT NOT NULL DEFAULT 'text'")])?;
+    }
+    return Ok(());
+}
+
+/// Rows saved before the dedup columns existed are seen once, at their timestamp
+fn add_dedup_columns(conn: &Connection) -> Result<()>{
+    for table in record_tables_of_kind(conn, SELECTOR_KIND)? {
+        add_missing_columns(conn, &table, &[("content_hash", "TEXT"), ("first_seen", "INTEGER"), ("last_seen", "INTEGER"),
+                                            ("seen_count", "INTEGER NOT NULL DEFAULT 1")])?;
+        let mut select = conn.prepare(&format!("SELECT id, url, selector, content, extract FROM {} WHERE content_hash IS NULL", quote_ident(&table)))?;
+        let rows = select.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, SelectorRecord{timestamp: 0, url: row.get(1)?, selector: row.get(2)?,
+                                                     content: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                                                     host: String::new(), extract: row.get(4)?}))
+        })?;
+        let mut update = conn.prepare(&format!("UPDATE {} SET content_hash = ?1, first_seen = timestamp, last_seen = timestamp WHERE id = ?2", quote_ident(&table)))?;
+        for row in rows {
+            let (id, record) = row?;
+            update.execute(params![record.content_hash(), id])?;
+        }
+        conn.execute(&format!("CREATE INDEX IF NOT EXISTS {} ON {} (content_hash)", quote_ident(&format!("{}_content_hash", table)), quote_ident(&table)), [])?;
+    }
+    return Ok(());
+}
+
+/// Rows saved before keep a NULL fetch_id
+fn add_runs_and_fetches(conn: &Connection) -> Result<()>{
+    create_runs_and_fetches_tables(conn)?;
+    let fetch_id_type = format!("INTEGER REFERENCES {}(id)", FETCHES_TABLE);
+    for (table, _) in record_tables(conn)? {
+        add_missing_columns(conn, &table, &[("fetch_id", fetch_id_type.as_str())])?;
+    }
+    return Ok(());
+}
+
+/// The selector was the css part only, the records of `a` and `a@href` with the same content had the same hash
+fn add_extraction_to_selectors(conn: &Connection) -> Result<()>{
+    for table in record_tables_of_kind(conn, SELECTOR_KIND)? {
+        let mut select = conn.prepare(&format!("SELECT id, url, selector, content, extract FROM {} WHERE extract != 'text'", quote_ident(&table)))?;
+        let rows = select.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, SelectorRecord{timestamp: 0, url: row.get(1)?, selector: row.get(2)?,
+                                                     content: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                                                     host: String::new(), extract: row.get(4)?}))
+        })?;
+        let mut update = conn.prepare(&format!("UPDATE {} SET selector = ?1, content_hash = ?2 WHERE id = ?3", quote_ident(&table)))?;
+        for row in rows {
+            let (id, mut record) = row?;
+            // an unknown extract is left as it is
+            if let Some(extraction) = Extraction::from_name(&record.extract) {
+                record.selector = SelectorSpec{css: record.selector, extraction}.to_string();
+                update.execute(params![record.selector, record.content_hash(), id])?;
+            }
+        }
+    }
+    return Ok(());
+}
+
+fn create_runs_and_fetches_tables(conn: &Connection) -> Result<()>{
+    conn.execute_batch(&format!("
+        CREATE TABLE IF NOT EXISTS {runs} (
+            id              INTEGER PRIMARY KEY,
+            started_at      INTEGER NOT NULL,
+            ended_at        INTEGER,
+            config_hash     TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS {fetches} (
+            id              INTEGER PRIMARY KEY,
+            run_id          INTEGER NOT NULL REFERENCES {runs}(id),
+            url             TEXT NOT NULL,
+            final_url       TEXT,
+            status_code     INTEGER,
+            latency_ms      INTEGER NOT NULL,
+            byte_size       INTEGER,
+            content_type    TEXT,
+            fetched_at      INTEGER NOT NULL,
+            attempts        INTEGER NOT NULL,
+            error           TEXT
+        );
+        CREATE INDEX IF NOT EXISTS {fetches}_run_id ON {fetches} (run_id);", runs = RUNS_TABLE, fetches = FETCHES_TABLE))?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_legacy_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // the first selector record table, before the host column, and a structured one
+        conn.execute_batch("CREATE TABLE selector_record (id INTEGER PRIMARY KEY, timestamp TIMESTAMP, url TEXT NOT NULL,
+                                                          selector TEXT NOT NULL, content TEXT);
+                            INSERT INTO selector_record (timestamp, url, selector, content) VALUES (50, 'https://www.cnews.fr/sport', 'h3', 'Headline');
+                            CREATE TABLE article (id INTEGER PRIMARY KEY, timestamp TIMESTAMP, url TEXT NOT NULL, selector TEXT NOT NULL,
+                                                  host TEXT NOT NULL, title TEXT);").unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+        assert!(matches!(check_schema(&conn), Err(ScrapeError::Schema(_))));
+
+        let applied: Vec<u32> = migrate(&mut conn).unwrap().iter().map(|m| m.version).collect();
+        assert_eq!(applied, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+        check_schema(&conn).unwrap();
+        assert_eq!(record_tables(&conn).unwrap(), vec![(String::from("article"), String::from(STRUCTURED_KIND)),
+                                                       (String::from("selector_record"), String::from(SELECTOR_KIND))]);
+
+        let (host, extract, first_seen, has_hash): (String, String, u64, bool) = conn.query_row(
+            "SELECT host, extract, first_seen, content_hash IS NOT NULL FROM selector_record", [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))).unwrap();
+        assert_eq!((host.as_str(), extract.as_str(), first_seen, has_hash), ("www.cnews.fr", "text", 50, true));
+        let article_columns = get_col_names(&conn, "article").unwrap();
+        assert!(!article_columns.contains(&String::from("extract")));
+        assert!(article_columns.contains(&String::from("fetch_id")));
+
+        // the internal tables aren't record tables
+        assert_eq!(get_db_table_names(&conn).unwrap(), vec!["selector_record", "article"]);
+        assert!(migrate(&mut conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_extraction_selectors() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        conn.execute_batch("CREATE TABLE links (id INTEGER PRIMARY KEY, timestamp TIMESTAMP, url TEXT NOT NULL, selector TEXT NOT NULL, content TEXT,
+                                                host TEXT NOT NULL DEFAULT '', extract TEXT NOT NULL DEFAULT 'text', content_hash TEXT);
+                            INSERT INTO links (timestamp, url, selector, content, extract) VALUES
+                                (50, 'https://www.cnews.fr/', 'a', 'https://www.cnews.fr/sport', 'attr:href'),
+                                (50, 'https://www.cnews.fr/', 'a', 'Sport', 'text');").unwrap();
+        register_record_table(&conn, "links", SELECTOR_KIND).unwrap();
+        conn.execute(&format!("DELETE FROM {} WHERE version = 6", SCHEMA_TABLE), []).unwrap();
+        migrate(&mut conn).unwrap();
+
+        let mut stmt = conn.prepare("SELECT url, selector, content, extract, content_hash FROM links ORDER BY id").unwrap();
+        let rows: Vec<(SelectorRecord, Option<String>)> = stmt.query_map([], |row| {
+            Ok((SelectorRecord{timestamp: 0, url: row.get(0)?, selector: row.get(1)?, content: row.get(2)?, host: String::new(), extract: row.get(3)?},
+                row.get(4)?))
+        }).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(rows[0].0.selector, "a@href");
+        assert_eq!(rows[0].1, Some(rows[0].0.content_hash()));
+        // the text records keep their selector and hash
+        assert_eq!((rows[1].0.selector.as_str(), rows[1].1.as_deref()), ("a", None));
+    }
+
+    #[test]
+    fn test_newer_database_is_refused() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        conn.execute(&format!("INSERT INTO {} (version, description, applied_at) VALUES (?1, 'From the future', 0)", SCHEMA_TABLE),
+                     [latest_version() + 1]).unwrap();
+        assert!(matches!(migrate(&mut conn), Err(ScrapeError::Schema(_))));
+        let error = check_schema(&conn).unwrap_err().to_string();
+        assert!(error.contains("newer"), "{}", error);
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use log::*;
+
+use super::config::{HostLimits, PolitenessSettings};
+
+struct HostGate{
+    in_flight: Arc<Semaphore>,
+    min_delay: Mutex<Duration>,
+    last_request: AsyncMutex<Option<Instant>>
+}
+
+/// Held while a request is running, releases the global and the host slots once dropped.
+pub struct PolitePermit{
+    _host: OwnedSemaphorePermit,
+    _global: OwnedSemaphorePermit
+}
+
+/// Shared by all the request tasks of a run to cap the load sent to each host.
+pub struct Politeness{
+    settings: PolitenessSettings,
+    global: Arc<Semaphore>,
+    hosts: Mutex<HashMap<String, Arc<HostGate>>>
+}
+
+impl Politeness{
+    pub fn new(settings: &PolitenessSettings) -> Politeness{
+        return Politeness{settings: settings.clone(),
+                          global: Arc::new(Semaphore::new(settings.max_concurrency)),
+                          hosts: Mutex::new(HashMap::new())};
+    }
+
+    fn get_host_gate(&self, host: &str) -> Arc<HostGate>{
+        let mut hosts = self.hosts.lock().unwrap();
+        let gate = hosts.entry(host.to_string()).or_insert_with(|| {
+            let limits: &HostLimits = self.settings.limits_for(host);
+            Arc::new(HostGate{in_flight: Arc::new(Semaphore::new(limits.max_in_flight)),
+                              min_delay: Mutex::new(limits.min_delay),
+                              last_request: AsyncMutex::new(None)})
+        });
+        return gate.clone();
+    }
+
+    /// Used for robots.txt Crawl-delay : the configured delay is only replaced by a longer one.
+    pub fn raise_min_delay(&self, host: &str, delay: Duration){
+        let gate = self.get_host_gate(host);
+        let mut min_delay = gate.min_delay.lock().unwrap();
+        if delay > *min_delay {
+            info!("Minimum delay between requests to {} raised to {:?}", host, delay);
+            *min_delay = delay;
+        }
+    }
+
+    /// Waits for a free slot on the host, then for its minimum delay, then for a global slot.
+    /// The host slot is taken first so that waiting tasks don't hold global slots other hosts could use.
+    /// The request time is stamped once both slots are held, a long wait for the global slot can't shorten the next delay.
+    pub async fn acquire(&self, host: &str) -> PolitePermit{
+        let gate = self.get_host_gate(host);
+        let host_permit = gate.in_flight.clone().acquire_owned().await.expect("host semaphore closed");
+
+        let mut last_request = gate.last_request.lock().await;
+        if let Some(last) = *last_request {
+            let next_allowed = last + *gate.min_delay.lock().unwrap();
+            if next_allowed > Instant::now() {
+                debug!("Waiting {:?} before next request to {}", next_allowed - Instant::now(), host);
+                tokio::time::sleep_until(next_allowed).await;
+            }
+        }
+        let global_permit = self.global.clone().acquire_owned().await.expect("global semaphore closed");
+        *last_request = Some(Instant::now());
+        return PolitePermit{_host: host_permit, _global: global_permit};
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_acquire_respects_host_limits() {
+        let mut settings = PolitenessSettings::default();
+        settings.hosts.insert(String::from("slow.host"), HostLimits{max_in_flight: 1, min_delay: Duration::from_millis(100)});
+        let politeness = Politeness::new(&settings);
+
+        let start = Instant::now();
+        let first = politeness.acquire("slow.host").await;
+        // the single slot is taken, the other host is still free
+        let _other = politeness.acquire("other.host").await;
+        drop(first);
+        let _second = politeness.acquire("slow.host").await;
+        assert!(start.elapsed() >= Duration::from_millis(100), "{:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn test_min_delay_counts_from_global_slot() {
+        let mut settings = PolitenessSettings{max_concurrency: 1, ..PolitenessSettings::default()};
+        settings.hosts.insert(String::from("slow.host"), HostLimits{max_in_flight: 2, min_delay: Duration::from_millis(100)});
+        let politeness = Arc::new(Politeness::new(&settings));
+
+        // the other host holds the only global slot longer than the delay
+        let other = politeness.acquire("other.host").await;
+        let waiting = politeness.clone();
+        let first = tokio::spawn(async move {
+            drop(waiting.acquire("slow.host").await);
+            Instant::now()
+        });
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        drop(other);
+        let first_sent = first.await.unwrap();
+        let _second = politeness.acquire("slow.host").await;
+        assert!(first_sent.elapsed() >= Duration::from_millis(100), "{:?}", first_sent.elapsed());
+    }
+}
@@ -0,0 +1,358 @@
+use std::io::{self, Write};
+
+use clap::ArgEnum;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use rusqlite::Connection;
+use rusqlite::types::Value;
+
+use super::db_utils::{check_table_name, quote_ident};
+use super::errors::{Result, ScrapeError};
+use super::scrap_utils::SelectorRecord;
+
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq)]
+pub enum OrderBy{
+    Timestamp,
+    Host,
+    Url,
+    Selector,
+    Content
+}
+
+impl OrderBy{
+    fn column(&self) -> &'static str{
+        return match self {
+            OrderBy::Timestamp => "timestamp",
+            OrderBy::Host => "host",
+            OrderBy::Url => "url",
+            OrderBy::Selector => "selector",
+            OrderBy::Content => "content",
+        };
+    }
+}
+
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat{
+    Table,
+    Json,
+    /// One json object per line
+    Jsonl,
+    Csv
+}
+
+/// Filters of the `query` command, all the given ones must match
+#[derive(Debug, Clone)]
+pub struct RecordQuery{
+    pub host: Option<String>,
+    /// SQLite GLOB pattern, like `https://www.cnews.fr/*`
+    pub url_pattern: Option<String>,
+    pub selector: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub contains: Option<String>,
+    pub content_regex: Option<Regex>,
+    pub order_by: OrderBy,
+    pub descending: bool,
+    pub limit: Option<usize>
+}
+
+impl Default for RecordQuery{
+    fn default() -> RecordQuery{
+        return RecordQuery{host: None, url_pattern: None, selector: None, since: None, until: None, contains: None,
+                           content_regex: None, order_by: OrderBy::Timestamp, descending: true, limit: None};
+    }
+}
+
+impl RecordQuery{
+    /// The regex is applied on the rows returned by SQLite, the limit is then applied by `run`
+    fn to_sql(&self, table: &str) -> (String, Vec<Value>){
+        let mut conditions: Vec<&str> = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+        if let Some(host) = &self.host {
+            conditions.push("host = ?");
+            values.push(Value::Text(host.clone()));
+        }
+        if let Some(pattern) = &self.url_pattern {
+            conditions.push("url GLOB ?");
+            values.push(Value::Text(pattern.clone()));
+        }
+        if let Some(selector) = &self.selector {
+            conditions.push("selector = ?");
+            values.push(Value::Text(selector.clone()));
+        }
+        if let Some(since) = self.since {
+            conditions.push("timestamp >= ?");
+            values.push(Value::Integer(since as i64));
+        }
+        if let Some(until) = self.until {
+            conditions.push("timestamp <= ?");
+            values.push(Value::Integer(until as i64));
+        }
+        if let Some(contains) = &self.contains {
+            conditions.push("instr(content, ?) > 0");
+            values.push(Value::Text(contains.clone()));
+        }
+
+        let mut sql = format!("SELECT timestamp, url, selector, content, host, extract FROM {}", quote_ident(table));
+        if !conditions.is_empty() {
+            sql.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        sql.push_str(&format!(" ORDER BY {} {}, id {}", self.order_by.column(), direction, direction));
+        if let (Some(limit), None) = (self.limit, &self.content_regex) {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+        return (sql, values);
+    }
+
+    pub fn run(&self, conn: &Connection, table: &str) -> Result<Vec<SelectorRecord>>{
+        let mut records = Vec::new();
+        self.for_each(conn, table, |record| {
+            records.push(record);
+            Ok(())
+        })?;
+        return Ok(records);
+    }
+
+    /// Gives the matching records one by one as SQLite reads them, for a table that doesn't fit in memory.
+    /// Returns the number of records.
+    pub fn for_each<F: FnMut(SelectorRecord) -> Result<()>>(&self, conn: &Connection, table: &str, mut f: F) -> Result<usize>{
+        check_table_name(table)?;
+        let (sql, values) = self.to_sql(table);
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(values), |row| {
+            Ok(SelectorRecord{timestamp: row.get(0)?, url: row.get(1)?, selector: row.get(2)?,
+                              content: row.get::<_, Option<String>>(3)?.unwrap_or_default(), host: row.get(4)?, extract: row.get(5)?})
+        })?;
+
+        let mut count = 0;
+        for record in rows {
+            if self.limit.map(|limit| count >= limit).unwrap_or(false) {
+                break;
+            }
+            let record = record?;
+            if self.content_regex.as_ref().map(|re| re.is_match(&record.content)).unwrap_or(true) {
+                f(record)?;
+                count += 1;
+            }
+        }
+        return Ok(count);
+    }
+}
+
+/// Parses "24h", "30m", "7d" or "90s" as a time before now, a unix timestamp, or a "YYYY-MM-DD" UTC date
+pub fn parse_time(value: &str, now: u64) -> Result<u64>{
+    let invalid = || ScrapeError::Config(format!("invalid time '{}', expected a duration like 24h, a unix timestamp or a YYYY-MM-DD date", value));
+    let value = value.trim();
+    if let Ok(timestamp) = value.parse::<u64>() {
+        return Ok(timestamp);
+    }
+    let unit_secs = match value.chars().last() {
+        Some('s') => Some(1),
+        Some('m') => Some(60),
+        Some('h') => Some(3600),
+        Some('d') => Some(86400),
+        _ => None
+    };
+    if let Some(unit_secs) = unit_secs {
+        let count: u64 = value[..value.len() - 1].parse().map_err(|_| invalid())?;
+        let secs = count.checked_mul(unit_secs).ok_or_else(invalid)?;
+        return Ok(now.saturating_sub(secs));
+    }
+    let parts: Vec<&str> = value.split('-').collect();
+    if let [year, month, day] = parts.as_slice() {
+        // the years before 1970 are left out by the timestamp conversion, u32 keeps days_from_civil from overflowing
+        let year: u32 = year.parse().map_err(|_| invalid())?;
+        let month: u32 = month.parse().map_err(|_| invalid())?;
+        let day: u32 = day.parse().map_err(|_| invalid())?;
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return Err(invalid());
+        }
+        let days = days_from_civil(year as i64, month, day);
+        return days.checked_mul(86400).and_then(|secs| u64::try_from(secs).ok()).ok_or_else(invalid);
+    }
+    return Err(invalid());
+}
+
+fn days_in_month(year: u32, month: u32) -> u32{
+    return match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    };
+}
+
+/// Days since 1970-01-01 of a proleptic gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64{
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return era * 146097 + day_of_era - 719468;
+}
+
+/// UTC (year, month, day) of a unix timestamp, the inverse of `days_from_civil`
+pub fn civil_from_timestamp(timestamp: u64) -> (i64, u32, u32){
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    return (year, month, day);
+}
+
+/// A row printed by `print_rows`, its serde serialization is used for json and csv
+pub trait TableRow: Serialize{
+    /// Headers of the table format, the last column isn't padded
+    fn table_headers() -> Vec<&'static str>;
+    fn table_cells(&self) -> Vec<String>;
+}
+
+impl TableRow for SelectorRecord{
+    fn table_headers() -> Vec<&'static str>{
+        return vec!["Timestamp", "Host", "Selector", "Content"];
+    }
+
+    fn table_cells(&self) -> Vec<String>{
+        return vec![self.timestamp.to_string(), self.host.clone(), self.selector.clone(), self.content.clone()];
+    }
+}
+
+fn write_table<W: Write, T: TableRow>(out: &mut W, rows: &[T]) -> io::Result<()>{
+    let headers: Vec<String> = T::table_headers().into_iter().map(String::from).collect();
+    let rows: Vec<Vec<String>> = rows.iter().map(|r| r.table_cells()).collect();
+    return write_text_table(out, &headers, &rows);
+}
+
+/// Columns aligned on their widest cell, the last column isn't padded
+pub fn write_text_table<W: Write>(out: &mut W, headers: &[String], rows: &[Vec<String>]) -> io::Result<()>{
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(headers).chain(rows.iter().map(|r| r.as_slice())) {
+        let last = row.len() - 1;
+        let cells: Vec<String> = row.iter().enumerate()
+            .map(|(i, cell)| if i == last { cell.clone() } else { format!("{:width$}", cell, width = widths[i]) })
+            .collect();
+        writeln!(out, "{}", cells.join("  "))?;
+    }
+    return Ok(());
+}
+
+fn write_rows<W: Write, T: TableRow>(out: &mut W, rows: &[T], format: OutputFormat) -> Result<()>{
+    match format {
+        OutputFormat::Table => write_table(out, rows)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, rows).map_err(io::Error::from)?;
+            writeln!(out)?;
+        },
+        OutputFormat::Jsonl => for row in rows {
+            serde_json::to_writer(&mut *out, row).map_err(io::Error::from)?;
+            writeln!(out)?;
+        },
+        OutputFormat::Csv => {
+            let mut wtr = csv::Writer::from_writer(&mut *out);
+            for row in rows {
+                wtr.serialize(row)?;
+            }
+            wtr.flush()?;
+        }
+    }
+    return Ok(());
+}
+
+/// Writes the rows to stdout
+pub fn print_rows<T: TableRow>(rows: &[T], format: OutputFormat) -> Result<()>{
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    return ignore_broken_pipe(write_rows(&mut out, rows, format));
+}
+
+/// A closed pipe (`| head`) isn't an error when printing to stdout
+pub fn ignore_broken_pipe(result: Result<()>) -> Result<()>{
+    return match result {
+        Err(ScrapeError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        Err(ScrapeError::Csv(e)) if matches!(e.kind(), csv::ErrorKind::Io(io_e) if io_e.kind() == io::ErrorKind::BrokenPipe) => Ok(()),
+        other => other
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::config::TableOptions;
+    use super::super::migrations::migrate;
+    use super::super::db_utils::save_selector_records_to_db;
+
+    fn record(timestamp: u64, url: &str, selector: &str, content: &str) -> SelectorRecord{
+        return SelectorRecord::new(timestamp, url.to_string(), selector.to_string(), content.to_string(), String::from("text"));
+    }
+
+    #[test]
+    fn test_record_query() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let records = vec![record(100, "https://www.cnews.fr/", "h3", "Old headline"),
+                           record(200, "https://www.cnews.fr/", "h3", "Recent headline 1"),
+                           record(300, "https://www.cnews.fr/sport", "h3", "Recent headline 2"),
+                           record(300, "https://www.cnews.fr/", "a", "A link"),
+                           record(300, "https://www.google.fr/", "h3", "Other host headline")];
+        save_selector_records_to_db(&mut conn, "selector_record", &records, &TableOptions::default(), None).unwrap();
+
+        let query = RecordQuery{host: Some(String::from("www.cnews.fr")), selector: Some(String::from("h3")), since: Some(150), ..RecordQuery::default()};
+        let contents: Vec<String> = query.run(&conn, "selector_record").unwrap().into_iter().map(|r| r.content).collect();
+        assert_eq!(contents, vec!["Recent headline 2", "Recent headline 1"]);
+
+        let query = RecordQuery{url_pattern: Some(String::from("https://www.cnews.fr/*")), contains: Some(String::from("headline")),
+                                content_regex: Some(Regex::new(r"\d$").unwrap()), descending: false, limit: Some(1), ..RecordQuery::default()};
+        let contents: Vec<String> = query.run(&conn, "selector_record").unwrap().into_iter().map(|r| r.content).collect();
+        assert_eq!(contents, vec!["Recent headline 1"]);
+
+        let query = RecordQuery{content_regex: Some(Regex::new("headline").unwrap()), limit: Some(0), ..RecordQuery::default()};
+        assert!(query.run(&conn, "selector_record").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("24h", 100_000).unwrap(), 100_000 - 86400);
+        assert_eq!(parse_time("1700000000", 0).unwrap(), 1700000000);
+        assert_eq!(parse_time("2024-03-01", 0).unwrap(), 1709251200);
+        assert!(parse_time("yesterday", 0).is_err());
+        assert!(parse_time("2024-13-01", 0).is_err());
+        assert!(parse_time("2024-02-31", 0).is_err());
+        assert!(parse_time("2023-02-29", 0).is_err());
+        assert_eq!(parse_time("2024-02-29", 0).unwrap(), 1709164800);
+        assert!(parse_time("999999999999999999d", 100).is_err());
+        assert!(parse_time("99999999999999999999-01-01", 0).is_err());
+        assert!(parse_time("1969-12-31", 0).is_err());
+        assert_eq!(civil_from_timestamp(1709251200 + 3600), (2024, 3, 1));
+        assert_eq!(civil_from_timestamp(1709251199), (2024, 2, 29));
+    }
+
+    #[test]
+    fn test_write_records_formats() {
+        let records = vec![record(100, "https://www.cnews.fr/", "h3", "Headline")];
+        let mut out: Vec<u8> = Vec::new();
+        write_rows(&mut out, &records, OutputFormat::Csv).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Timestamp,Url,Selector,Content,Host,Extract\n100,https://www.cnews.fr/,h3,Headline,www.cnews.fr,text\n");
+
+        let mut out: Vec<u8> = Vec::new();
+        write_rows(&mut out, &records, OutputFormat::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json[0]["Content"], "Headline");
+
+        let mut out: Vec<u8> = Vec::new();
+        write_rows(&mut out, &records, OutputFormat::Table).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().nth(1).unwrap(), "100        www.cnews.fr  h3        Headline");
+    }
+}
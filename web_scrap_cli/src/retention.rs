@@ -0,0 +1,236 @@
+use std::io::Write;
+
+use rusqlite::{params, Connection};
+
+use super::config::RetentionSettings;
+use super::db_utils::{get_row_count, quote_ident};
+use super::errors::{Result, ScrapeError};
+use super::migrations::{record_tables, FETCHES_TABLE, RUNS_TABLE, SELECTOR_KIND};
+use super::stats::{db_file_size, format_size};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Records deleted from a table by each rule
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TablePrune{
+    pub table: String,
+    pub expired: usize,
+    pub old_runs: usize,
+    pub duplicates: usize,
+    pub remaining: usize
+}
+
+impl TablePrune{
+    pub fn deleted(&self) -> usize{
+        return self.expired + self.old_runs + self.duplicates;
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PruneReport{
+    pub tables: Vec<TablePrune>,
+    /// Rows of the `fetches` and `runs` tables that no remaining record references
+    pub fetches: usize,
+    pub runs: usize,
+    /// Size of the database file before and after the VACUUM, None without one
+    pub vacuum: Option<(Option<u64>, Option<u64>)>
+}
+
+impl PruneReport{
+    pub fn write(&self, out: &mut dyn Write, dry_run: bool) -> Result<()>{
+        let verb = if dry_run { "would be deleted" } else { "deleted" };
+        for table in &self.tables {
+            writeln!(out, "'{}' : {} records {} ({} expired, {} from older runs, {} duplicates), {} remaining",
+                     table.table, table.deleted(), verb, table.expired, table.old_runs, table.duplicates, table.remaining)?;
+        }
+        writeln!(out, "{} fetches and {} runs without records {}", self.fetches, self.runs, verb)?;
+        if let Some((before, after)) = self.vacuum {
+            let size = |s: Option<u64>| s.map(format_size).unwrap_or_else(|| String::from("no file"));
+            writeln!(out, "Vacuum : {} -> {}", size(before), size(after))?;
+        }
+        if dry_run {
+            writeln!(out, "Dry run, nothing was deleted")?;
+        }
+        return Ok(());
+    }
+}
+
+/// Selector record tables to prune, all of them when none is given
+pub fn selector_tables(conn: &Connection, tables: &[String]) -> Result<Vec<String>>{
+    let selector_tables: Vec<String> = record_tables(conn)?.into_iter().filter(|(_, kind)| kind == SELECTOR_KIND).map(|(name, _)| name).collect();
+    if tables.is_empty() {
+        return Ok(selector_tables);
+    }
+    for table in tables {
+        if !selector_tables.contains(table) {
+            return Err(ScrapeError::Config(format!("'{}' is not a selector record table", table)));
+        }
+    }
+    return Ok(tables.to_vec());
+}
+
+/// Applies the rules to each table in a single transaction, a dry run rolls it back so that
+/// the counts are the ones of a real run. The VACUUM comes after the commit.
+pub fn prune(conn: &mut Connection, path: &str, tables: &[String], settings: &RetentionSettings, now: u64, dry_run: bool) -> Result<PruneReport>{
+    let mut report = PruneReport::default();
+    let tx = conn.transaction()?;
+    for table in tables {
+        report.tables.push(prune_table(&tx, table, settings, now)?);
+    }
+    (report.fetches, report.runs) = delete_unreferenced_history(&tx)?;
+    if dry_run {
+        tx.rollback()?;
+        return Ok(report);
+    }
+    tx.commit()?;
+    if settings.vacuum {
+        let before = db_file_size(path);
+        // the rebuilt pages go through the WAL file of a WAL database
+        conn.execute_batch("VACUUM; PRAGMA wal_checkpoint(TRUNCATE);")?;
+        report.vacuum = Some((before, db_file_size(path)));
+    }
+    return Ok(report);
+}
+
+fn prune_table(conn: &Connection, table: &str, settings: &RetentionSettings, now: u64) -> Result<TablePrune>{
+    let quoted = quote_ident(table);
+    let mut pruned = TablePrune{table: table.to_string(), ..TablePrune::default()};
+    if let Some(days) = settings.keep_days {
+        // a record seen again since is kept
+        let cutoff = now.saturating_sub(days.saturating_mul(SECONDS_PER_DAY));
+        pruned.expired = conn.execute(&format!("DELETE FROM {} WHERE COALESCE(last_seen, timestamp) < ?1", quoted), [cutoff])?;
+    }
+    if let Some(runs) = settings.keep_runs {
+        pruned.old_runs = delete_old_runs(conn, &quoted, runs)?;
+    }
+    if settings.dedup {
+        pruned.duplicates = delete_duplicates(conn, &quoted)?;
+    }
+    pruned.remaining = get_row_count(conn, table)? as usize;
+    return Ok(pruned);
+}
+
+/// The runs of a url are its successful fetches, or the timestamps of its records for the ones
+/// without fetches such as imported records. A record is kept when it was seen since the
+/// oldest of the last `runs` runs of its url.
+fn delete_old_runs(conn: &Connection, quoted_table: &str, runs: u64) -> Result<usize>{
+    let sql = format!("
+        WITH source_runs AS (
+            SELECT url, MAX(fetched_at) AS at FROM {fetches}
+            WHERE error IS NULL AND url IN (SELECT url FROM {table}) GROUP BY url, run_id
+            UNION ALL
+            SELECT DISTINCT url, timestamp FROM {table}
+            WHERE url NOT IN (SELECT url FROM {fetches} WHERE error IS NULL)
+        ),
+        ranked AS (
+            SELECT url, at, ROW_NUMBER() OVER (PARTITION BY url ORDER BY at DESC) AS position FROM source_runs
+        )
+        DELETE FROM {table}
+        WHERE COALESCE(last_seen, timestamp) < (SELECT at FROM ranked WHERE ranked.url = {table}.url AND position = ?1)",
+        fetches = FETCHES_TABLE, table = quoted_table);
+    return Ok(conn.execute(&sql, params![runs])?);
+}
+
+/// Deletes the fetches that no record of any table references, then the runs left without fetches.
+/// A run still going may not have saved the records of its fetches yet, it is left out.
+fn delete_unreferenced_history(conn: &Connection) -> Result<(usize, usize)>{
+    let referenced: Vec<String> = record_tables(conn)?.into_iter()
+        .map(|(table, _)| format!("SELECT fetch_id FROM {} WHERE fetch_id IS NOT NULL", quote_ident(&table)))
+        .collect();
+    let unreferenced = if referenced.is_empty() { String::new() } else { format!("AND id NOT IN ({})", referenced.join(" UNION ")) };
+    let fetches = conn.execute(&format!("DELETE FROM {fetches} WHERE run_id IN (SELECT id FROM {runs} WHERE ended_at IS NOT NULL) {unreferenced}",
+                                        fetches = FETCHES_TABLE, runs = RUNS_TABLE, unreferenced = unreferenced), [])?;
+    let runs = conn.execute(&format!("DELETE FROM {runs} WHERE ended_at IS NOT NULL AND id NOT IN (SELECT run_id FROM {fetches})",
+                                     fetches = FETCHES_TABLE, runs = RUNS_TABLE), [])?;
+    return Ok((fetches, runs));
+}
+
+/// Keeps the oldest record of each content hash, it takes the `last_seen` and `seen_count` of the deleted ones
+fn delete_duplicates(conn: &Connection, quoted_table: &str) -> Result<usize>{
+    let first = format!("(SELECT d.id FROM {table} d WHERE d.content_hash = {table}.content_hash ORDER BY d.timestamp, d.id LIMIT 1)",
+                        table = quoted_table);
+    conn.execute(&format!("
+        UPDATE {table} SET
+            last_seen = (SELECT MAX(COALESCE(d.last_seen, d.timestamp)) FROM {table} d WHERE d.content_hash = {table}.content_hash),
+            seen_count = (SELECT SUM(d.seen_count) FROM {table} d WHERE d.content_hash = {table}.content_hash)
+        WHERE content_hash IN (SELECT content_hash FROM {table} GROUP BY content_hash HAVING COUNT(*) > 1) AND id = {first}",
+        table = quoted_table, first = first), [])?;
+    return Ok(conn.execute(&format!("DELETE FROM {} WHERE content_hash IS NOT NULL AND id != {}", quoted_table, first), [])?);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::config::{StorageSettings, TableOptions};
+    use super::super::db_utils::{finish_run, open_db, save_fetch, save_selector_records_to_db, start_run};
+    use super::super::scrap_utils::{FetchInfo, SelectorRecord};
+
+    const URL: &str = "https://www.cnews.fr/";
+
+    fn record(timestamp: u64, content: &str) -> SelectorRecord{
+        return SelectorRecord::new(timestamp, String::from(URL), String::from("h3"), String::from(content), String::from("text"));
+    }
+
+    /// One run per timestamp, each saving the given contents without dedup
+    fn scraped_db(runs: &[(u64, &[&str])]) -> Connection{
+        let mut conn = open_db(":memory:", &StorageSettings::default()).unwrap();
+        let options = TableOptions{dedup: false, ..TableOptions::default()};
+        for (timestamp, contents) in runs {
+            let run_id = start_run(&conn, *timestamp, "hash").unwrap();
+            let info = FetchInfo{url: String::from(URL), fetched_at: *timestamp, status_code: Some(200), ..FetchInfo::default()};
+            let fetch_id = save_fetch(&conn, run_id, &info).unwrap();
+            let records: Vec<SelectorRecord> = contents.iter().map(|c| record(*timestamp, c)).collect();
+            save_selector_records_to_db(&mut conn, "news", &records, &options, Some(fetch_id)).unwrap();
+            finish_run(&conn, run_id, *timestamp).unwrap();
+        }
+        return conn;
+    }
+
+    fn contents(conn: &Connection) -> Vec<(String, u64, u32)>{
+        let mut stmt = conn.prepare("SELECT content, last_seen, seen_count FROM news ORDER BY id").unwrap();
+        return stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap().map(|r| r.unwrap()).collect();
+    }
+
+    #[test]
+    fn test_prune_rules() {
+        let day = SECONDS_PER_DAY;
+        let mut conn = scraped_db(&[(day, &["A", "B"]), (2 * day, &["A"]), (3 * day, &["A", "C"]), (4 * day, &["C"])]);
+        let tables = vec![String::from("news")];
+
+        let expire = RetentionSettings{keep_days: Some(3), ..RetentionSettings::default()};
+        let report = prune(&mut conn, ":memory:", &tables, &expire, 4 * day + 1, true).unwrap();
+        assert_eq!(report.tables, vec![TablePrune{table: String::from("news"), expired: 2, remaining: 4, ..TablePrune::default()}]);
+        assert_eq!(contents(&conn).len(), 6);
+
+        let settings = RetentionSettings{keep_runs: Some(3), dedup: true, ..RetentionSettings::default()};
+        let report = prune(&mut conn, ":memory:", &tables, &settings, 4 * day + 1, false).unwrap();
+        assert_eq!(report.tables[0], TablePrune{table: String::from("news"), old_runs: 2, duplicates: 2, remaining: 2, ..TablePrune::default()});
+        assert_eq!(contents(&conn), vec![(String::from("A"), 3 * day, 2), (String::from("C"), 4 * day, 2)]);
+        // the records left come from the 2nd and 3rd runs
+        assert_eq!((report.fetches, report.runs), (2, 2));
+        let run_ids: Vec<i64> = conn.prepare("SELECT id FROM runs ORDER BY id").unwrap()
+            .query_map([], |row| row.get(0)).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(run_ids, vec![2, 3]);
+
+        // a run still going keeps its fetches
+        let run_id = start_run(&conn, 5 * day, "hash").unwrap();
+        save_fetch(&conn, run_id, &FetchInfo{url: String::from(URL), fetched_at: 5 * day, ..FetchInfo::default()}).unwrap();
+        let report = prune(&mut conn, ":memory:", &tables, &RetentionSettings::default(), 5 * day, false).unwrap();
+        assert_eq!((report.fetches, report.runs), (0, 0));
+    }
+
+    #[test]
+    fn test_prune_huge_keep_days() {
+        let mut conn = scraped_db(&[(100, &["A"])]);
+        let settings = RetentionSettings{keep_days: Some(u64::MAX), ..RetentionSettings::default()};
+        let report = prune(&mut conn, ":memory:", &[String::from("news")], &settings, 200, false).unwrap();
+        assert_eq!(report.tables[0].expired, 0);
+    }
+
+    #[test]
+    fn test_selector_tables() {
+        let conn = scraped_db(&[(100, &["A"])]);
+        assert_eq!(selector_tables(&conn, &[]).unwrap(), vec![String::from("news")]);
+        assert!(selector_tables(&conn, &[String::from("runs")]).is_err());
+    }
+}
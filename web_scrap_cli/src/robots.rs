@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::Client;
+use tokio::sync::OnceCell;
+use url::Url;
+use log::*;
+
+use super::config::{FetchSettings, RobotsSettings};
+use super::errors::{Result, ScrapeError};
+use super::politeness::Politeness;
+use super::scrap_utils::get_body_from;
+
+/// A longer Crawl-delay would let a single host stall the run
+const MAX_CRAWL_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+struct RobotsRule{
+    allow: bool,
+    pattern: String
+}
+
+#[derive(Debug, Clone, Default)]
+struct RobotsGroup{
+    agents: Vec<String>,
+    rules: Vec<RobotsRule>,
+    crawl_delay: Option<Duration>
+}
+
+/// Rules of a robots.txt file that apply to one user-agent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RobotsRules{
+    rules: Vec<RobotsRule>,
+    pub crawl_delay: Option<Duration>
+}
+
+impl RobotsRules{
+    pub fn allow_all() -> RobotsRules{
+        return RobotsRules::default();
+    }
+
+    pub fn disallow_all() -> RobotsRules{
+        return RobotsRules{rules: vec![RobotsRule{allow: false, pattern: String::from("/")}], crawl_delay: None};
+    }
+
+    /// Parses a robots.txt content, keeping the group matching best the given user-agent
+    /// (longest matching name, '*' otherwise). Groups with the same name are merged.
+    pub fn parse(content: &str, user_agent: &str) -> RobotsRules{
+        let mut groups: Vec<RobotsGroup> = Vec::new();
+        let mut current = RobotsGroup::default();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let (key, value) = match line.split_once(':') {
+                Some((k, v)) => (k.trim().to_lowercase(), v.trim()),
+                None => continue
+            };
+            match key.as_str() {
+                "user-agent" => {
+                    // a user-agent line after some rules starts a new group
+                    if !current.rules.is_empty() || current.crawl_delay.is_some() {
+                        groups.push(std::mem::take(&mut current));
+                    }
+                    current.agents.push(value.to_lowercase());
+                },
+                // an empty Disallow means everything is allowed
+                "allow" | "disallow" if !current.agents.is_empty() && !value.is_empty() => {
+                    current.rules.push(RobotsRule{allow: key == "allow", pattern: value.to_string()});
+                },
+                "crawl-delay" if !current.agents.is_empty() => {
+                    // a delay too large for a Duration is clamped like any other long delay
+                    current.crawl_delay = value.parse::<f64>().ok()
+                                               .filter(|d| !d.is_nan() && *d >= 0.0)
+                                               .map(|d| Duration::try_from_secs_f64(d).unwrap_or(Duration::MAX))
+                                               .map(|d| {
+                                                   if d > MAX_CRAWL_DELAY {
+                                                       warn!("Crawl-delay of {} seconds limited to {:?}", value, MAX_CRAWL_DELAY);
+                                                   }
+                                                   d.min(MAX_CRAWL_DELAY)
+                                               });
+                },
+                _ => {}
+            }
+        }
+        if !current.agents.is_empty() {
+            groups.push(current);
+        }
+
+        let user_agent = user_agent.to_lowercase();
+        let agent_match_len = |group: &RobotsGroup| group.agents.iter()
+            .filter(|a| a.as_str() != "*" && user_agent.contains(a.as_str()))
+            .map(|a| a.len())
+            .max();
+        let best_len = groups.iter().filter_map(agent_match_len).max();
+        let selected: Vec<&RobotsGroup> = match best_len {
+            Some(len) => groups.iter().filter(|g| agent_match_len(g) == Some(len)).collect(),
+            None => groups.iter().filter(|g| g.agents.iter().any(|a| a == "*")).collect()
+        };
+
+        let mut robots_rules = RobotsRules::default();
+        for group in selected {
+            robots_rules.rules.extend(group.rules.iter().cloned());
+            robots_rules.crawl_delay = robots_rules.crawl_delay.max(group.crawl_delay);
+        }
+        return robots_rules;
+    }
+
+    /// The longest matching rule wins, Allow wins in case of equality. No matching rule means allowed.
+    pub fn is_allowed(&self, path: &str) -> bool{
+        let mut best: Option<&RobotsRule> = None;
+        for rule in &self.rules {
+            if !pattern_matches(&rule.pattern, path) {
+                continue;
+            }
+            best = match best {
+                Some(b) if b.pattern.len() > rule.pattern.len() => Some(b),
+                Some(b) if b.pattern.len() == rule.pattern.len() && b.allow => Some(b),
+                _ => Some(rule)
+            };
+        }
+        return best.map(|r| r.allow).unwrap_or(true);
+    }
+}
+
+/// robots.txt path pattern : a prefix match supporting '*' wildcards and a '$' end anchor
+fn pattern_matches(pattern: &str, path: &str) -> bool{
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern, false)
+    };
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let mut rest = path;
+    for (i, part) in parts.iter().enumerate() {
+        if i == 0 {
+            match rest.strip_prefix(part) {
+                Some(r) => rest = r,
+                None => return false
+            }
+        } else if i == parts.len() - 1 && anchored {
+            return rest.ends_with(part);
+        } else {
+            match rest.find(part) {
+                Some(pos) => rest = &rest[pos + part.len()..],
+                None => return false
+            }
+        }
+    }
+    return !anchored || rest.is_empty();
+}
+
+/// robots.txt of every origin met during a run, each one is fetched once.
+pub struct RobotsCache{
+    settings: RobotsSettings,
+    origins: Mutex<HashMap<String, Arc<OnceCell<RobotsRules>>>>
+}
+
+impl RobotsCache{
+    pub fn new(settings: &RobotsSettings) -> RobotsCache{
+        return RobotsCache{settings: settings.clone(), origins: Mutex::new(HashMap::new())};
+    }
+
+    async fn fetch_rules(&self, client: &Client, robots_url: &str, fetch_settings: &FetchSettings, politeness: &Politeness, host: &str) -> Result<RobotsRules>{
+        let permit = politeness.acquire(host).await;
+        let content = get_body_from(client, robots_url, fetch_settings).await;
+        drop(permit);
+        return match content {
+            Ok(content) => Ok(RobotsRules::parse(&content, &self.settings.user_agent)),
+            // no robots.txt (404, 403...) means no restriction
+            Err(ScrapeError::HttpStatus{status, ..}) if status.is_client_error() => {
+                info!("No robots.txt for {} ({}), all urls allowed", host, status);
+                Ok(RobotsRules::allow_all())
+            },
+            // a robots.txt unavailable because of a server error must be considered as a full disallow
+            Err(ScrapeError::HttpStatus{status, ..}) => {
+                warn!("Couldn't get {} ({}), all urls of {} are disallowed", robots_url, status, host);
+                Ok(RobotsRules::disallow_all())
+            },
+            // the host can't be reached at all, it's reported as is and not cached
+            Err(e) => Err(e)
+        };
+    }
+
+    /// Returns an error if the url is disallowed for the configured user-agent,
+    /// and applies the Crawl-delay of the host to the politeness limits.
+    pub async fn check(&self, client: &Client, url: &str, fetch_settings: &FetchSettings, politeness: &Politeness) -> Result<()>{
+        if !self.settings.enabled {
+            return Ok(());
+        }
+        let parsed_url = Url::parse(url).map_err(|e| ScrapeError::InvalidUrl{url: url.to_string(), reason: e.to_string()})?;
+        let host = parsed_url.host_str().unwrap_or_default().to_string();
+        if self.settings.ignore_hosts.contains(&host) {
+            debug!("robots.txt ignored for {}", host);
+            return Ok(());
+        }
+
+        let origin = parsed_url.origin().ascii_serialization();
+        let cell = self.origins.lock().unwrap().entry(origin.clone()).or_default().clone();
+        let robots_url = format!("{}/robots.txt", origin);
+        let rules = cell.get_or_try_init(|| self.fetch_rules(client, &robots_url, fetch_settings, politeness, &host)).await?;
+
+        if let Some(delay) = rules.crawl_delay {
+            politeness.raise_min_delay(&host, delay);
+        }
+        let mut path = parsed_url.path().to_string();
+        if let Some(query) = parsed_url.query() {
+            path = format!("{}?{}", path, query);
+        }
+        if !rules.is_allowed(&path) {
+            return Err(ScrapeError::RobotsDisallowed{url: url.to_string(), user_agent: self.settings.user_agent.clone()});
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS_TXT: &str = r#"
+    # comments are ignored
+    User-agent: *
+    Disallow: /private/
+    Allow: /private/public-page
+    Disallow: /*.pdf$
+
+    User-agent: web_scrap_cli
+    Disallow: /news/
+    Crawl-delay: 2
+    "#;
+
+    #[test]
+    fn test_parse_selects_best_user_agent_group() {
+        let rules = RobotsRules::parse(ROBOTS_TXT, "web_scrap_cli/0.1.0");
+        assert!(!rules.is_allowed("/news/today"));
+        assert!(rules.is_allowed("/private/"));
+        assert_eq!(rules.crawl_delay, Some(Duration::from_secs(2)));
+
+        let rules = RobotsRules::parse(ROBOTS_TXT, "other_bot");
+        assert!(rules.is_allowed("/news/today"));
+        assert!(!rules.is_allowed("/private/secret"));
+        assert!(rules.is_allowed("/private/public-page"));
+        assert_eq!(rules.crawl_delay, None);
+    }
+
+    #[test]
+    fn test_parse_limits_crawl_delay() {
+        let delay = |value: &str| RobotsRules::parse(&format!("User-agent: *\nCrawl-delay: {}", value), "web_scrap_cli").crawl_delay;
+        assert_eq!(delay("0.5"), Some(Duration::from_millis(500)));
+        assert_eq!(delay("86400"), Some(MAX_CRAWL_DELAY));
+        assert_eq!(delay("1e300"), Some(MAX_CRAWL_DELAY));
+        assert_eq!(delay("-1"), None);
+        assert_eq!(delay("NaN"), None);
+    }
+
+    #[test]
+    fn test_pattern_matches() {
+        assert!(pattern_matches("/", "/anything"));
+        assert!(pattern_matches("/*.pdf$", "/docs/file.pdf"));
+        assert!(!pattern_matches("/*.pdf$", "/docs/file.pdf?page=2"));
+        assert!(pattern_matches("/a*c", "/abbbc/d"));
+        assert!(!pattern_matches("/news", "/"));
+    }
+}
@@ -1,13 +1,21 @@
-use std::error::Error;
-use scraper::{Html, Selector};
-use reqwest::Client;
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime};
+use scraper::{ElementRef, Html, Selector};
+use reqwest::{Client, Response};
+use reqwest::header::{CONTENT_TYPE, RETRY_AFTER};
 use select::document::Document;
 use select::predicate::Name;
-use serde::{Serialize, Deserialize};
-use regex::{RegexSet, Regex};
+use serde::{Serialize, Serializer, Deserialize};
+use serde::ser::SerializeMap;
+use regex::Regex;
+use sha2::{Digest, Sha256};
 use url::Url;
+use log::*;
 
 use  super::file_utils::get_timestamp_now;
+use super::errors::{Result, ScrapeError};
+use super::config::FetchSettings;
+use super::filters::{ContentFilter, FilterStats};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -16,76 +24,604 @@ pub struct SelectorRecord {
     pub url: String,
     pub selector: String,
     pub content: String,
-    pub host: String
+    /// Older csv files may not have it, `import` takes it from the url
+    #[serde(default)]
+    pub host: String,
+    /// Same for the extraction, `import` takes it from the selector
+    #[serde(default)]
+    pub extract: String
 }
 
 impl SelectorRecord{
-    pub fn new(timestamp: u64, url: String, selector: String, content: String) -> SelectorRecord{
+    pub fn new(timestamp: u64, url: String, selector: String, content: String, extract: String) -> SelectorRecord{
         let host:String = get_host_from_url(&url).unwrap();
-        return SelectorRecord{timestamp, url, selector, content, host}
+        return SelectorRecord{timestamp, url, selector, content, host, extract}
     }
+
+    /// Identity of the record for the dedup : sha256 of its url, selector and content.
+    /// The selector is the full spec with its extraction, `a` and `a::html` give separate records.
+    pub fn content_hash(&self) -> String{
+        let mut hasher = Sha256::new();
+        for part in [&self.url, &self.selector, &self.content] {
+            hasher.update(part.as_bytes());
+            hasher.update([0u8]);
+        }
+        return format!("{:x}", hasher.finalize());
+    }
+}
+
+/// What is taken from each element matched by a CSS selector
+#[derive(Debug, Clone, PartialEq)]
+pub enum Extraction{
+    Text,
+    Attr(String),
+    InnerHtml,
+    OuterHtml
+}
+
+impl fmt::Display for Extraction{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self {
+            Extraction::Text => write!(f, "text"),
+            Extraction::Attr(name) => write!(f, "attr:{}", name),
+            Extraction::InnerHtml => write!(f, "html"),
+            Extraction::OuterHtml => write!(f, "outer_html"),
+        }
+    }
+}
+
+impl Extraction{
+    /// Inverse of the display, used for the `extract` column
+    pub fn from_name(name: &str) -> Option<Extraction>{
+        return match name {
+            "text" => Some(Extraction::Text),
+            "html" => Some(Extraction::InnerHtml),
+            "outer_html" => Some(Extraction::OuterHtml),
+            _ => name.strip_prefix("attr:").filter(|attr| !attr.is_empty()).map(|attr| Extraction::Attr(attr.to_string()))
+        };
+    }
+}
+
+// attributes holding an url, resolved against the page url when relative
+const URL_ATTRIBUTES: [&str; 2] = ["href", "src"];
+
+/// A CSS selector with an optional extraction suffix :
+/// `h3` or `h3::text` for the text, `a@href` for an attribute,
+/// `div::html` for the inner html and `div::outer_html` for the element html.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectorSpec{
+    pub css: String,
+    pub extraction: Extraction
+}
+
+impl SelectorSpec{
+    pub fn parse(spec: &str) -> Result<SelectorSpec>{
+        let spec = spec.trim();
+        let (css, extraction) = if let Some(css) = spec.strip_suffix("::text") {
+            (css, Extraction::Text)
+        } else if let Some(css) = spec.strip_suffix("::outer_html") {
+            (css, Extraction::OuterHtml)
+        } else if let Some(css) = spec.strip_suffix("::html") {
+            (css, Extraction::InnerHtml)
+        } else {
+            // an '@' inside an attribute selector like [href*="@"] is not an extraction suffix
+            match spec.rfind('@') {
+                Some(pos) if !spec[pos..].contains(']') => {
+                    let attr = &spec[pos + 1..];
+                    let valid_attr = !attr.is_empty() && attr.chars().all(|c| c.is_ascii_alphanumeric() || "-_:".contains(c));
+                    if !valid_attr {
+                        return Err(ScrapeError::SelectorParse{selector: spec.to_string(), reason: format!("invalid attribute name '{}'", attr)});
+                    }
+                    (&spec[..pos], Extraction::Attr(attr.to_lowercase()))
+                },
+                _ => (spec, Extraction::Text)
+            }
+        };
+        let selector_spec = SelectorSpec{css: css.trim().to_string(), extraction};
+        selector_spec.to_selector()?;
+        return Ok(selector_spec);
+    }
+
+    pub fn to_selector(&self) -> Result<Selector>{
+        return parse_selector(&self.css);
+    }
+
+    /// False for the text of the elements, the default
+    pub fn has_extraction(&self) -> bool{
+        return self.extraction != Extraction::Text;
+    }
+
+    /// Value of a matched element, None when the attribute is missing
+    fn extract_from(&self, element: &ElementRef, page_url: Option<&Url>) -> Option<String>{
+        return match &self.extraction {
+            Extraction::Text => Some(element.text().collect()),
+            Extraction::InnerHtml => Some(element.inner_html()),
+            Extraction::OuterHtml => Some(element.html()),
+            Extraction::Attr(name) => {
+                let value = element.value().attr(name)?.trim();
+                match page_url {
+                    Some(base) if URL_ATTRIBUTES.contains(&name.as_str()) => {
+                        Some(base.join(value).map(|u| u.to_string()).unwrap_or_else(|_| value.to_string()))
+                    },
+                    _ => Some(value.to_string())
+                }
+            }
+        };
+    }
+}
+
+/// The spec a record keeps as its selector, `h3::text` is written `h3`
+impl fmt::Display for SelectorSpec{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match &self.extraction {
+            Extraction::Text => write!(f, "{}", self.css),
+            Extraction::Attr(name) => write!(f, "{}@{}", self.css, name),
+            Extraction::InnerHtml => write!(f, "{}::html", self.css),
+            Extraction::OuterHtml => write!(f, "{}::outer_html", self.css),
+        }
+    }
+}
+
+/// Type of a structured item field, text values are converted when saved
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldType{
+    Text,
+    Integer,
+    Real
+}
+
+impl FieldType{
+    pub fn parse(name: &str) -> Result<FieldType>{
+        return match name {
+            "text" => Ok(FieldType::Text),
+            "integer" => Ok(FieldType::Integer),
+            "real" => Ok(FieldType::Real),
+            _ => Err(ScrapeError::Config(format!("unknown field type '{}', expected text, integer or real", name)))
+        };
+    }
+
+    pub fn sql_type(&self) -> &'static str{
+        return match self {
+            FieldType::Text => "TEXT",
+            FieldType::Integer => "INTEGER",
+            FieldType::Real => "REAL",
+        };
+    }
+}
+
+/// Columns of every structured record table, a field can't use their names
+pub const STRUCTURED_COLUMNS: [&str; 6] = ["id", "timestamp", "url", "selector", "host", "fetch_id"];
+
+/// A named column of a structured item, its selector is applied inside the item element
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldSpec{
+    pub name: String,
+    pub selector: String,
+    pub field_type: FieldType
+}
+
+impl FieldSpec{
+    pub fn new(name: &str, selector: &str, field_type: FieldType) -> Result<FieldSpec>{
+        let valid_name = name.chars().next().map(|c| c.is_ascii_alphabetic() || c == '_').unwrap_or(false)
+                         && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name {
+            return Err(ScrapeError::Config(format!("invalid field name '{}', only letters, digits and '_' are allowed", name)));
+        }
+        // column names are case-insensitive in SQLite
+        if STRUCTURED_COLUMNS.contains(&name.to_ascii_lowercase().as_str()) {
+            return Err(ScrapeError::Config(format!("invalid field name '{}', {} are columns of every structured table",
+                                                   name, STRUCTURED_COLUMNS.join(", "))));
+        }
+        SelectorSpec::parse(selector)?;
+        return Ok(FieldSpec{name: name.to_string(), selector: selector.to_string(), field_type});
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue{
+    Null,
+    Text(String),
+    Integer(i64),
+    Real(f64)
+}
+
+impl FieldValue{
+    /// Keeps only what looks like a number, "1 234 vues" gives 1234 and "3,5" gives 3.5
+    fn from_text(text: &str, field_type: FieldType) -> FieldValue{
+        let number_text = |extra: &[char]| text.chars()
+                                               .filter(|c| c.is_ascii_digit() || *c == '-' || extra.contains(c))
+                                               .map(|c| if c == ',' { '.' } else { c })
+                                               .collect::<String>();
+        return match field_type {
+            FieldType::Text => FieldValue::Text(text.to_string()),
+            FieldType::Integer => number_text(&[]).parse::<i64>().map(FieldValue::Integer).unwrap_or(FieldValue::Null),
+            FieldType::Real => number_text(&['.', ',']).parse::<f64>().map(FieldValue::Real).unwrap_or(FieldValue::Null),
+        };
+    }
+}
+
+impl fmt::Display for FieldValue{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self {
+            FieldValue::Null => Ok(()),
+            FieldValue::Text(v) => write!(f, "{}", v),
+            FieldValue::Integer(v) => write!(f, "{}", v),
+            FieldValue::Real(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// One item (article, card...) of a page with a value for each of its named fields
+#[derive(Clone, Debug)]
+pub struct StructuredRecord{
+    pub timestamp: u64,
+    pub url: String,
+    pub selector: String,
+    pub host: String,
+    pub fields: Vec<(String, FieldValue)>
+}
+
+impl Serialize for FieldValue{
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>{
+        return match self {
+            FieldValue::Null => serializer.serialize_none(),
+            FieldValue::Text(v) => serializer.serialize_str(v),
+            FieldValue::Integer(v) => serializer.serialize_i64(*v),
+            FieldValue::Real(v) => serializer.serialize_f64(*v),
+        };
+    }
+}
+
+/// Same keys as a SelectorRecord, followed by the fields
+impl Serialize for StructuredRecord{
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>{
+        let mut map = serializer.serialize_map(Some(4 + self.fields.len()))?;
+        map.serialize_entry("Timestamp", &self.timestamp)?;
+        map.serialize_entry("Url", &self.url)?;
+        map.serialize_entry("Selector", &self.selector)?;
+        map.serialize_entry("Host", &self.host)?;
+        for (name, value) in &self.fields {
+            map.serialize_entry(name, value)?;
+        }
+        return map.end();
+    }
+}
+
+/// Records of one UrlSelectorPair, structured when the pair has fields
+#[derive(Clone, Debug)]
+pub enum PairRecords{
+    Selector(Vec<SelectorRecord>),
+    Structured(Vec<StructuredRecord>)
 }
 
-pub async fn get_body_from(client: &Client, url: &str) -> String{
-    let response = client.get(url).send().await.unwrap().text().await;  
-    return response.expect("Error in the response");     
+impl PairRecords{
+    pub fn len(&self) -> usize{
+        return match self {
+            PairRecords::Selector(records) => records.len(),
+            PairRecords::Structured(records) => records.len(),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool{
+        return self.len() == 0;
+    }
+
+    pub fn host(&self) -> Option<&str>{
+        return match self {
+            PairRecords::Selector(records) => records.first().map(|r| r.host.as_str()),
+            PairRecords::Structured(records) => records.first().map(|r| r.host.as_str()),
+        };
+    }
+}
+
+/// HTTP metadata of a page fetch, saved in the `fetches` table
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FetchInfo{
+    pub url: String,
+    pub fetched_at: u64,
+    /// The fields of the response are the ones of the last attempt, None when no response came back
+    pub status_code: Option<u16>,
+    /// Url of the response, after the redirects
+    pub final_url: Option<String>,
+    pub content_type: Option<String>,
+    /// Size of the decoded body in bytes
+    pub byte_size: Option<u64>,
+    /// Duration of the last attempt, up to the whole body
+    pub latency: Duration,
+    pub attempts: u32,
+    pub error: Option<String>
+}
+
+enum FetchAttempt{
+    Done(String),
+    Retry(ScrapeError, Option<Duration>),
+    Fail(ScrapeError)
+}
+
+async fn fetch_once(client: &Client, url: &str, settings: &FetchSettings, info: &mut FetchInfo) -> FetchAttempt{
+    let is_transient = |e: &reqwest::Error| e.is_timeout() || e.is_connect() || e.is_request() || e.is_body();
+    let start = Instant::now();
+    info.attempts += 1;
+    info.status_code = None;
+    info.final_url = None;
+    info.content_type = None;
+    info.byte_size = None;
+    let mut request = client.get(url).timeout(settings.read_timeout);
+    for (name, value) in &settings.headers {
+        request = request.header(name, value);
+    }
+    let response = match request.send().await {
+        Ok(r) => r,
+        Err(e) if is_transient(&e) => return FetchAttempt::Retry(ScrapeError::from(e), None),
+        Err(e) => return FetchAttempt::Fail(ScrapeError::from(e))
+    };
+    let status = response.status();
+    info.status_code = Some(status.as_u16());
+    info.final_url = Some(response.url().to_string());
+    info.content_type = response.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(String::from);
+    if !status.is_success() {
+        info.latency = start.elapsed();
+        let error = ScrapeError::HttpStatus{url: url.to_string(), status};
+        if settings.is_retryable_status(status.as_u16()) {
+            return FetchAttempt::Retry(error, get_retry_after(&response));
+        }
+        return FetchAttempt::Fail(error);
+    }
+    let body = response.text().await;
+    info.latency = start.elapsed();
+    return match body {
+        Ok(body) => {
+            info.byte_size = Some(body.len() as u64);
+            FetchAttempt::Done(body)
+        },
+        Err(e) if is_transient(&e) => FetchAttempt::Retry(ScrapeError::from(e), None),
+        Err(e) => FetchAttempt::Fail(ScrapeError::from(e))
+    };
+}
+
+/// Delay asked by the server, either as a number of seconds or as an http date
+fn get_retry_after(response: &Response) -> Option<Duration>{
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    return Some(date.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO));
+}
+
+/// Fetches the url with the retries of the settings, the metadata is returned even for a failed fetch
+pub async fn fetch_page(client: &Client, url: &str, settings: &FetchSettings) -> (FetchInfo, Result<String>){
+    let mut info = FetchInfo{url: url.to_string(), fetched_at: get_timestamp_now(), ..FetchInfo::default()};
+    loop {
+        match fetch_once(client, url, settings, &mut info).await {
+            FetchAttempt::Done(body) => {
+                info!("Fetched {} after {} attempt(s)", url, info.attempts);
+                return (info, Ok(body));
+            },
+            FetchAttempt::Retry(error, retry_after) if info.attempts <= settings.retries => {
+                // Retry-After is honoured but never waits longer than backoff_max
+                let delay = retry_after.unwrap_or_else(|| settings.backoff_delay(info.attempts - 1)).min(settings.backoff_max);
+                warn!("Attempt {}/{} failed for {} ({}), retrying in {:?}", info.attempts, settings.retries + 1, url, error, delay);
+                tokio::time::sleep(delay).await;
+            },
+            FetchAttempt::Retry(error, _) | FetchAttempt::Fail(error) => {
+                error!("Giving up on {} after {} attempt(s) : {}", url, info.attempts, error);
+                info.error = Some(error.to_string());
+                return (info, Err(error));
+            }
+        }
+    }
+}
+
+pub async fn get_body_from(client: &Client, url: &str, settings: &FetchSettings) -> Result<String>{
+    return fetch_page(client, url, settings).await.1;
 }
 
 pub async fn extract_all_links(content: &str) -> Vec<String>{
-    println!("Links in the page :\n");
+    eprintln!("Links in the page :\n");
     let links: Vec<String> = Document::from(content).find(Name("a"))                                  
                                   .filter_map(|n| n.attr("href"))
-                                  .map(|s| String::from(s))
+                                  .map(String::from)
                                   .collect::<Vec<String>>();
     return links;
 }
 
-pub async fn get_css_selector_items(content: &str,  selector: &str) -> Vec<String>{
-    let body_html = Html::parse_document(content);
-    let selector = Selector::parse(selector).unwrap();
+pub fn parse_selector(selector: &str) -> Result<Selector>{
+    return Selector::parse(selector).map_err(|e| ScrapeError::SelectorParse{
+        selector: selector.to_string(),
+        reason: format!("{:?}", e.kind)});
+}
+
+pub fn get_css_selector_items(body_html: &Html,  selector_spec: &SelectorSpec, page_url: &str) -> Result<Vec<String>>{
+    let selector = selector_spec.to_selector()?;
+    let page_url = Url::parse(page_url).ok();
 
     let mut results: Vec<String> = Vec::new();
 
     for item in body_html.select(&selector){
-        results.push(item.text().map(|s| s.to_string()).collect());
+        if let Some(value) = selector_spec.extract_from(&item, page_url.as_ref()) {
+            results.push(value);
+        }
     }    
-   return results;
+   return Ok(results);
     
 }
 
-pub fn get_host_from_url(url: &str) -> Result<String, Box<dyn Error>> {
-    let url = Url::parse(url)?;
-    let host: String = format!("{}", url.host().unwrap());
+pub fn get_host_from_url(url: &str) -> Result<String> {
+    let invalid_url = |reason: String| ScrapeError::InvalidUrl{url: url.to_string(), reason};
+    let parsed_url = Url::parse(url).map_err(|e| invalid_url(e.to_string()))?;
+    let host: String = match parsed_url.host() {
+        Some(h) => format!("{}", h),
+        None => return Err(invalid_url(String::from("no host found"))),
+    };
     //assert_eq!(url.scheme(), "ftp");
     //assert_eq!(url.host(), Some(Host::Domain("rust-lang.org")));
     //assert_eq!(url.port_or_known_default(), Some(21));
     Ok(host)
 }
 
-pub async fn extract_selector_records(content: &str, valid_url: &str, selector: &str) -> Result<Vec<SelectorRecord>, Box<dyn Error>>{    
-    println!("\nExtracting '{}' CSS Selector items ...", selector);  
+/// Items dropped by the filter are counted in `filter_stats`
+pub fn extract_selector_records(document: &Html, valid_url: &str, selector: &str, filter: &ContentFilter, filter_stats: &mut FilterStats) -> Result<Vec<SelectorRecord>>{    
+    eprintln!("\nExtracting '{}' CSS Selector items ...", selector);  
     let timestamp = get_timestamp_now();                                          
-    
-    // keep only text that match one of following regex 
-    let regex_set = RegexSet::new(&[
-        r"(?m)^[a-zA-Z]{4,}", // text with at least 4 letters
-        //r"(\w.+\s).+" // text with at least 2 words
-    ])?;
-    
-    let re = Regex::new(r"\s\s+").unwrap(); // to find multiple spaces and remove them
 
-    let text_items = get_css_selector_items(content, selector).await;
+    let selector_spec = SelectorSpec::parse(selector)?;
+    let (spec, extract) = (selector_spec.to_string(), selector_spec.extraction.to_string());
+    let items = get_css_selector_items(document, &selector_spec, valid_url)?;
     let mut records = Vec::new();
 
     //println!("Current timestamp={}", timestamp.as_secs_f32());
-    for item in &text_items{
-        if regex_set.is_match(item){
-            let cleaned_item = re.replace_all(item, " ").to_string();
-            println!("{}", cleaned_item);
-            records.push(SelectorRecord::new(timestamp, String::from(valid_url), String::from(selector), cleaned_item));
+    for item in &items{
+        // attributes and html are filtered but kept as they are
+        let item = match selector_spec.extraction {
+            Extraction::Text => filter.normalize(item),
+            _ => item.to_string()
+        };
+        if let Some(kept_item) = filter.apply(&item, filter_stats) {
+            debug!("{}", kept_item);
+            records.push(SelectorRecord::new(timestamp, String::from(valid_url), spec.clone(), kept_item, extract.clone()));
         }                        
     }
-    println!("-------------------\nFound {} items matching regex, for selector '{}' !", records.len(), selector);
+    eprintln!("-------------------\nFound {} items matching filters, for selector '{}' !", records.len(), selector);
     
     return Ok(records);
 }
+
+/// One StructuredRecord per element matching the item selector, a field without match is Null
+pub fn extract_structured_records(document: &Html, valid_url: &str, item_selector: &str, fields: &[FieldSpec]) -> Result<Vec<StructuredRecord>>{
+    eprintln!("\nExtracting '{}' items with {} fields ...", item_selector, fields.len());
+    let timestamp = get_timestamp_now();
+    let host = get_host_from_url(valid_url)?;
+    let page_url = Url::parse(valid_url).ok();
+    let item_css = parse_selector(item_selector)?;
+    let field_selectors = fields.iter()
+                                .map(|f| SelectorSpec::parse(&f.selector).and_then(|spec| Ok((spec.to_selector()?, spec))))
+                                .collect::<Result<Vec<(Selector, SelectorSpec)>>>()?;
+    let re = Regex::new(r"\s\s+").unwrap(); // to find multiple spaces and remove them
+
+    let mut records = Vec::new();
+    for item in document.select(&item_css) {
+        let mut values = Vec::new();
+        for (field, (selector, spec)) in fields.iter().zip(&field_selectors) {
+            let value = match item.select(selector).next().and_then(|e| spec.extract_from(&e, page_url.as_ref())) {
+                Some(text) => {
+                    let text = re.replace_all(text.trim(), " ").to_string();
+                    FieldValue::from_text(&text, field.field_type)
+                },
+                None => FieldValue::Null
+            };
+            values.push((field.name.clone(), value));
+        }
+        records.push(StructuredRecord{timestamp, url: valid_url.to_string(), selector: item_selector.to_string(), host: host.clone(), fields: values});
+    }
+    eprintln!("-------------------\nFound {} items !", records.len());
+    return Ok(records);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::config::FilterSettings;
+
+    #[test]
+    fn test_parse_selector_error() {
+        let result = parse_selector("h3[");
+        assert!(matches!(result, Err(ScrapeError::SelectorParse{..})), "{:?}", result);
+    }
+
+    #[test]
+    fn test_get_host_from_url_error() {
+        let result = get_host_from_url("not an url");
+        assert!(matches!(result, Err(ScrapeError::InvalidUrl{..})), "{:?}", result);
+        assert_eq!(get_host_from_url("https://www.cnews.fr/").unwrap(), "www.cnews.fr");
+    }
+
+    fn extract_with_default_filter(document: &Html, url: &str, selector: &str) -> Result<Vec<SelectorRecord>> {
+        let filter = ContentFilter::new(&FilterSettings::default()).unwrap();
+        return extract_selector_records(document, url, selector, &filter, &mut FilterStats::default());
+    }
+
+    #[test]
+    fn test_extract_selector_records_on_parsed_document() {
+        let document = Html::parse_document(r#"<html><body>
+            <h3>First headline</h3><h3>Second   headline</h3>
+            <p>Paragraph content</p>
+            </body></html>"#);
+        let url = "https://www.cnews.fr/";
+
+        let h3_records = extract_with_default_filter(&document, url, "h3").unwrap();
+        assert_eq!(h3_records.len(), 2);
+        assert_eq!(h3_records[1].content, "Second headline");
+        let p_records = extract_with_default_filter(&document, url, "p").unwrap();
+        assert_eq!(p_records.len(), 1);
+        assert_eq!(p_records[0].host, "www.cnews.fr");
+    }
+
+    #[test]
+    fn test_selector_spec_parse() {
+        assert_eq!(SelectorSpec::parse("a@href").unwrap(), SelectorSpec{css: String::from("a"), extraction: Extraction::Attr(String::from("href"))});
+        assert_eq!(SelectorSpec::parse("div.card::html").unwrap().extraction, Extraction::InnerHtml);
+        assert_eq!(SelectorSpec::parse("div::outer_html").unwrap().extraction, Extraction::OuterHtml);
+        assert_eq!(SelectorSpec::parse(r#"a[href*="@"]"#).unwrap().extraction, Extraction::Text);
+        assert!(SelectorSpec::parse("a@").is_err());
+    }
+
+    #[test]
+    fn test_extract_attributes_and_html() {
+        let document = Html::parse_document(r#"<html><body>
+            <a href="/news/1">One</a><a href="https://other.fr/2">Two</a>
+            <time datetime="2022-03-01">March</time>
+            <div><b>bold</b></div>
+            </body></html>"#);
+        let url = "https://www.cnews.fr/section/";
+
+        let links = extract_with_default_filter(&document, url, "a@href").unwrap();
+        assert_eq!(links.iter().map(|r| r.content.as_str()).collect::<Vec<&str>>(), vec!["https://www.cnews.fr/news/1", "https://other.fr/2"]);
+        assert_eq!(links[0].selector, "a@href");
+        assert_eq!(links[0].extract, "attr:href");
+        let dates = extract_with_default_filter(&document, url, "time@datetime").unwrap();
+        assert_eq!(dates[0].content, "2022-03-01");
+        let html = extract_with_default_filter(&document, url, "div::html").unwrap();
+        assert_eq!(html[0].content, "<b>bold</b>");
+        assert_eq!(html[0].selector, "div::html");
+        assert_ne!(html[0].content_hash(), SelectorRecord{selector: String::from("div"), ..html[0].clone()}.content_hash());
+        assert_eq!(extract_with_default_filter(&document, url, "time::text").unwrap()[0].selector, "time");
+    }
+
+    #[test]
+    fn test_extract_selector_records_keeps_accents_and_digits() {
+        let document = Html::parse_document(r#"<html><body>
+            <h3>Élections : les résultats</h3><h3>2024, l'année olympique</h3><h3>Ok</h3>
+            </body></html>"#);
+        let filter = ContentFilter::new(&FilterSettings::default()).unwrap();
+        let mut filter_stats = FilterStats::default();
+        let records = extract_selector_records(&document, "https://www.france24.com/fr/", "h3", &filter, &mut filter_stats).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(filter_stats.dropped["min_length 4"], 1);
+    }
+
+    #[test]
+    fn test_extract_structured_records() {
+        let document = Html::parse_document(r#"<html><body>
+            <article><h2>First   card</h2><a href="/card/1">Read</a><span class="views">1 234 vues</span></article>
+            <article><h2>Second card</h2></article>
+            </body></html>"#);
+        let fields = vec![
+            FieldSpec::new("title", "h2", FieldType::Text).unwrap(),
+            FieldSpec::new("link", "a@href", FieldType::Text).unwrap(),
+            FieldSpec::new("views", "span.views", FieldType::Integer).unwrap(),
+        ];
+        let records = extract_structured_records(&document, "https://www.cnews.fr/", "article", &fields).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].fields, vec![
+            (String::from("title"), FieldValue::Text(String::from("First card"))),
+            (String::from("link"), FieldValue::Text(String::from("https://www.cnews.fr/card/1"))),
+            (String::from("views"), FieldValue::Integer(1234)),
+        ]);
+        assert_eq!(records[1].fields[1].1, FieldValue::Null);
+        assert!(FieldSpec::new("bad name", "h2", FieldType::Text).is_err());
+        assert!(FieldSpec::new("url", "a@href", FieldType::Text).is_err());
+        assert!(FieldSpec::new("Timestamp", "time", FieldType::Text).is_err());
+        assert!(FieldSpec::new("url_path", "a@href", FieldType::Text).is_ok());
+    }
+}
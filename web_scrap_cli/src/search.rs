@@ -0,0 +1,137 @@
+use rusqlite::Connection;
+use rusqlite::types::Value;
+use serde::Serialize;
+
+use super::db_utils::{check_table_name, fts_table_name, has_fts_index, quote_ident};
+use super::errors::{Result, ScrapeError};
+use super::query::TableRow;
+
+/// A full-text search in the content of a selector record table
+#[derive(Debug, Clone)]
+pub struct SearchQuery{
+    /// FTS5 query : words, "a phrase", prefix*, AND / OR / NOT
+    pub terms: String,
+    pub host: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub limit: usize,
+    /// Inserted around the matched words of the snippet
+    pub highlight: (String, String)
+}
+
+/// A matching record, the best ones have the highest score
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SearchHit{
+    pub score: f64,
+    pub timestamp: u64,
+    pub url: String,
+    pub selector: String,
+    pub host: String,
+    pub snippet: String,
+    pub content: String
+}
+
+impl TableRow for SearchHit{
+    fn table_headers() -> Vec<&'static str>{
+        return vec!["Score", "Timestamp", "Host", "Selector", "Snippet"];
+    }
+
+    fn table_cells(&self) -> Vec<String>{
+        return vec![format!("{:.2}", self.score), self.timestamp.to_string(), self.host.clone(), self.selector.clone(), self.snippet.clone()];
+    }
+}
+
+impl SearchQuery{
+    /// Ranked with bm25, newest records first for the same rank
+    pub fn run(&self, conn: &Connection, table: &str) -> Result<Vec<SearchHit>>{
+        check_table_name(table)?;
+        if !has_fts_index(conn, table)? {
+            return Err(ScrapeError::Config(format!("table '{}' has no full-text index, create it with `search --create-index` or scrape with --fts", table)));
+        }
+        let fts = quote_ident(&fts_table_name(table));
+        let mut conditions = vec![format!("{} MATCH ?", fts)];
+        let mut values = vec![Value::Text(self.highlight.0.clone()), Value::Text(self.highlight.1.clone()), Value::Text(self.terms.clone())];
+        if let Some(host) = &self.host {
+            conditions.push(String::from("r.host = ?"));
+            values.push(Value::Text(host.clone()));
+        }
+        if let Some(since) = self.since {
+            conditions.push(String::from("r.timestamp >= ?"));
+            values.push(Value::Integer(since as i64));
+        }
+        if let Some(until) = self.until {
+            conditions.push(String::from("r.timestamp <= ?"));
+            values.push(Value::Integer(until as i64));
+        }
+        let sql = format!("SELECT -bm25({fts}), r.timestamp, r.url, r.selector, r.host, snippet({fts}, 0, ?, ?, '…', 12), r.content
+                           FROM {fts} JOIN {table} r ON r.id = {fts}.rowid
+                           WHERE {conditions}
+                           ORDER BY bm25({fts}), r.timestamp DESC
+                           LIMIT {limit}", fts = fts, table = quote_ident(table), conditions = conditions.join(" AND "), limit = self.limit);
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(values), |row| {
+            Ok(SearchHit{score: row.get(0)?, timestamp: row.get(1)?, url: row.get(2)?, selector: row.get(3)?,
+                         host: row.get(4)?, snippet: row.get(5)?, content: row.get(6)?})
+        })?;
+
+        let mut hits = Vec::new();
+        for hit in rows {
+            hits.push(hit?);
+        }
+        return Ok(hits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::config::TableOptions;
+    use super::super::migrations::migrate;
+    use super::super::db_utils::{create_fts_index, save_selector_records_to_db};
+    use super::super::scrap_utils::SelectorRecord;
+
+    fn record(timestamp: u64, url: &str, content: &str) -> SelectorRecord{
+        return SelectorRecord::new(timestamp, url.to_string(), String::from("h3"), content.to_string(), String::from("text"));
+    }
+
+    #[test]
+    fn test_search_ranking_and_filters() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        // rows saved before the index are indexed on creation, the later ones by the triggers
+        save_selector_records_to_db(&mut conn, "selector_record", &[record(100, "https://www.cnews.fr/", "Élections : les résultats")],
+                                    &TableOptions::default(), None).unwrap();
+        let options = TableOptions{fts: true, ..TableOptions::default()};
+        save_selector_records_to_db(&mut conn, "selector_record", &[record(200, "https://www.cnews.fr/", "Élections européennes, élections régionales"),
+                                                                    record(300, "https://www.google.fr/", "Les élections en direct"),
+                                                                    record(300, "https://www.cnews.fr/", "Météo du week-end")], &options, None).unwrap();
+
+        let mut query = SearchQuery{terms: String::from("élections"), host: None, since: None, until: None, limit: 10,
+                                    highlight: (String::from("["), String::from("]"))};
+        let hits = query.run(&conn, "selector_record").unwrap();
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0].content, "Élections européennes, élections régionales");
+        assert_eq!(hits[0].snippet, "[Élections] européennes, [élections] régionales");
+
+        query.host = Some(String::from("www.cnews.fr"));
+        query.since = Some(150);
+        let hits = query.run(&conn, "selector_record").unwrap();
+        assert_eq!(hits.len(), 1);
+
+        conn.execute("DELETE FROM selector_record WHERE timestamp = 200", []).unwrap();
+        assert_eq!(query.run(&conn, "selector_record").unwrap().len(), 0);
+        // creating an existing index does nothing
+        create_fts_index(&conn, "selector_record").unwrap();
+    }
+
+    #[test]
+    fn test_search_without_index() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        save_selector_records_to_db(&mut conn, "selector_record", &[record(100, "https://www.cnews.fr/", "Titre")], &TableOptions::default(), None).unwrap();
+        let query = SearchQuery{terms: String::from("titre"), host: None, since: None, until: None, limit: 10,
+                                highlight: (String::new(), String::new())};
+        assert!(matches!(query.run(&conn, "selector_record"), Err(ScrapeError::Config(_))));
+    }
+}
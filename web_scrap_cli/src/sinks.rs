@@ -0,0 +1,485 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use rusqlite::Connection;
+
+use super::config::{Config, CsvSettings, OutputSpec, RetentionSettings, StorageSettings, TableOptions, UrlSelectorPair};
+use super::db_utils::{finish_run, open_db, save_fetch, save_selector_records_to_db, save_structured_records_to_db,
+                      start_run, SaveOutcome};
+use super::errors::{Result, ScrapeError};
+use super::file_utils::get_timestamp_now;
+use super::retention::{prune, selector_tables};
+use super::query::{write_text_table, OutputFormat, TableRow};
+use super::stats::{db_stats, ReportFormat};
+use super::scrap_utils::{get_host_from_url, FetchInfo, PairRecords, SelectorRecord};
+
+/// The records of one pair, with what a sink may need to place them
+pub struct RecordBatch<'a>{
+    pub run_id: i64,
+    /// Position of the pair in the run, keeps the per pair file names unique
+    pub index: usize,
+    pub url_selector: &'a UrlSelectorPair,
+    pub table: &'a str,
+    pub table_options: &'a TableOptions,
+    pub records: &'a PairRecords
+}
+
+/// An output of the scraped records. `run` calls `start_run` once, then the writer thread calls for each page
+/// `page_fetched` followed by `write` for each of its pairs with records, `flush` after each batch of records,
+/// and `finish` at the end.
+pub trait RecordSink: Send{
+    /// Used in the logs
+    fn name(&self) -> String;
+
+    /// A sink keeping the runs returns the run id, the other sinks get it in the batches
+    fn start_run(&mut self, _started_at: u64, _config_hash: &str) -> Result<Option<i64>>{
+        return Ok(None);
+    }
+
+    /// Called whenever a request was sent, before the records of the page
+    fn page_fetched(&mut self, _fetch: &FetchInfo) -> Result<()>{
+        return Ok(());
+    }
+
+    /// Only a sink with dedup can count records already seen, the other ones count them all as new
+    fn write(&mut self, batch: &RecordBatch) -> Result<SaveOutcome>;
+
+    /// Commits what was written since the last flush
+    fn flush(&mut self) -> Result<()>{
+        return Ok(());
+    }
+
+    fn finish(&mut self, _ended_at: u64) -> Result<()>{
+        return Ok(());
+    }
+}
+
+/// Records, runs and fetches saved in a database, each batch of the writer in its own transaction
+pub struct SqliteSink{
+    path: String,
+    conn: Connection,
+    print_stats: bool,
+    /// Applied at the end of the run when `after_scrape` is set
+    retention: RetentionSettings,
+    run_id: Option<i64>,
+    fetch_id: Option<i64>
+}
+
+impl SqliteSink{
+    pub fn new(path: &str, settings: &StorageSettings, print_stats: bool, retention: &RetentionSettings) -> Result<SqliteSink>{
+        return Ok(SqliteSink{path: path.to_string(), conn: open_db(path, settings)?, print_stats, retention: retention.clone(), run_id: None, fetch_id: None});
+    }
+
+    /// The transaction of a batch starts with its first fetch or records
+    fn begin_batch(&self) -> Result<()>{
+        if self.conn.is_autocommit() {
+            self.conn.execute_batch("BEGIN")?;
+        }
+        return Ok(());
+    }
+}
+
+impl RecordSink for SqliteSink{
+    fn name(&self) -> String{
+        return format!("sqlite '{}'", self.path);
+    }
+
+    fn start_run(&mut self, started_at: u64, config_hash: &str) -> Result<Option<i64>>{
+        self.run_id = Some(start_run(&self.conn, started_at, config_hash)?);
+        return Ok(self.run_id);
+    }
+
+    fn page_fetched(&mut self, fetch: &FetchInfo) -> Result<()>{
+        self.fetch_id = None;
+        if let Some(run_id) = self.run_id {
+            self.begin_batch()?;
+            self.fetch_id = Some(save_fetch(&self.conn, run_id, fetch)?);
+        }
+        return Ok(());
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<SaveOutcome>{
+        self.begin_batch()?;
+        return match batch.records {
+            PairRecords::Selector(r) => save_selector_records_to_db(&mut self.conn, batch.table, r, batch.table_options, self.fetch_id),
+            PairRecords::Structured(r) => {
+                save_structured_records_to_db(&mut self.conn, batch.table, &batch.url_selector.fields, r, self.fetch_id)?;
+                Ok(SaveOutcome::all_new(r.len()))
+            }
+        };
+    }
+
+    fn flush(&mut self) -> Result<()>{
+        if !self.conn.is_autocommit() {
+            if let Err(e) = self.conn.execute_batch("COMMIT") {
+                // the batch is lost, the rollback lets `finish` still end the run
+                let _ = self.conn.execute_batch("ROLLBACK");
+                return Err(e.into());
+            }
+        }
+        return Ok(());
+    }
+
+    fn finish(&mut self, ended_at: u64) -> Result<()>{
+        self.flush()?;
+        if let Some(run_id) = self.run_id {
+            finish_run(&self.conn, run_id, ended_at)?;
+        }
+        if self.retention.after_scrape && self.retention.has_rules() {
+            let tables = selector_tables(&self.conn, &[])?;
+            prune(&mut self.conn, &self.path, &tables, &self.retention, ended_at, false)?.write(&mut io::stderr(), false)?;
+        }
+        if self.print_stats {
+            // stdout is kept for the records
+            db_stats(&self.conn, &self.path)?.write(&mut io::stderr(), ReportFormat::Text)?;
+        }
+        return Ok(());
+    }
+}
+
+/// Csv files named from the `CsvSettings` template, `records_<host>_<run id>_<pair index>.csv` by default
+pub struct CsvSink{
+    settings: CsvSettings,
+    started_at: u64,
+    /// Files already written during this run, the next pairs are appended to them
+    written: HashSet<PathBuf>
+}
+
+impl CsvSink{
+    pub fn new(settings: CsvSettings) -> CsvSink{
+        return CsvSink{settings, started_at: get_timestamp_now(), written: HashSet::new()};
+    }
+
+    /// Header row of an existing file, None when it's empty
+    fn existing_headers(&self, path: &Path) -> Result<Option<Vec<String>>>{
+        let mut reader = csv::ReaderBuilder::new().has_headers(false).delimiter(self.settings.delimiter).quote(self.settings.quote)
+                                                 .from_path(path)?;
+        return Ok(reader.records().next().transpose()?.map(|r| r.iter().map(String::from).collect()));
+    }
+}
+
+impl RecordSink for CsvSink{
+    fn name(&self) -> String{
+        return format!("csv files in '{}'", self.settings.dir);
+    }
+
+    fn start_run(&mut self, started_at: u64, _config_hash: &str) -> Result<Option<i64>>{
+        self.started_at = started_at;
+        self.written.clear();
+        return Ok(None);
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<SaveOutcome>{
+        let host = get_host_from_url(&batch.url_selector.url)?;
+        let name = self.settings.file_name(&host, &batch.url_selector.selector, batch.table, batch.run_id, batch.index, self.started_at)?;
+        let path = Path::new(&self.settings.dir).join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let (headers, rows) = text_rows(batch.records, OutputFormat::Csv);
+        let append = (self.settings.append || self.written.contains(&path)) && path.is_file();
+        let write_headers = match append {
+            true => match self.existing_headers(&path)? {
+                Some(existing) if existing != headers => return Err(ScrapeError::Io(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("'{}' has other columns than the records of {}, add {{selector}} or {{table}} to the csv name", path.display(), batch.url_selector.url)))),
+                Some(_) => false,
+                None => true
+            },
+            false => true
+        };
+        let file = match append {
+            true => OpenOptions::new().append(true).open(&path)?,
+            false => File::create(&path)?
+        };
+        let mut wtr = csv::WriterBuilder::new().has_headers(false).delimiter(self.settings.delimiter).quote(self.settings.quote)
+                                               .quote_style(self.settings.quote_style.to_csv()).from_writer(BufWriter::new(file));
+        if write_headers {
+            wtr.write_record(&headers)?;
+        }
+        for row in rows {
+            wtr.write_record(&row)?;
+        }
+        wtr.flush()?;
+        self.written.insert(path);
+        return Ok(SaveOutcome::all_new(batch.records.len()));
+    }
+}
+
+/// Each record as a json object on one line
+fn json_lines(records: &PairRecords) -> Result<Vec<String>>{
+    let lines = match records {
+        PairRecords::Selector(r) => r.iter().map(serde_json::to_string).collect::<serde_json::Result<Vec<String>>>(),
+        PairRecords::Structured(r) => r.iter().map(serde_json::to_string).collect()
+    };
+    return Ok(lines.map_err(io::Error::from)?);
+}
+
+/// Headers and rows of the csv and table formats. The csv columns are the ones of the csv files,
+/// the table ones are shortened like the `query` command does.
+fn text_rows(records: &PairRecords, format: OutputFormat) -> (Vec<String>, Vec<Vec<String>>){
+    let table = format == OutputFormat::Table;
+    return match records {
+        PairRecords::Selector(r) if table => (SelectorRecord::table_headers().into_iter().map(String::from).collect(),
+                                              r.iter().map(|record| record.table_cells()).collect()),
+        PairRecords::Selector(r) => (["Timestamp", "Url", "Selector", "Content", "Host", "Extract"].into_iter().map(String::from).collect(),
+                                     r.iter().map(|record| vec![record.timestamp.to_string(), record.url.clone(), record.selector.clone(),
+                                                                record.content.clone(), record.host.clone(), record.extract.clone()]).collect()),
+        PairRecords::Structured(r) => {
+            let leading: &[&str] = if table { &["Timestamp", "Host", "Selector"] } else { &["Timestamp", "Url", "Selector", "Host"] };
+            let mut headers: Vec<String> = leading.iter().map(|h| h.to_string()).collect();
+            headers.extend(r.first().into_iter().flat_map(|record| record.fields.iter().map(|(name, _)| name.clone())));
+            let rows = r.iter().map(|record| {
+                let mut row = if table { vec![record.timestamp.to_string(), record.host.clone(), record.selector.clone()] }
+                              else { vec![record.timestamp.to_string(), record.url.clone(), record.selector.clone(), record.host.clone()] };
+                row.extend(record.fields.iter().map(|(_, value)| value.to_string()));
+                row
+            }).collect();
+            (headers, rows)
+        }
+    };
+}
+
+/// Records of the whole run in one of the output formats, to a file or to stdout.
+/// jsonl and csv are written as the pages come, json is closed and table is printed by `finish`.
+/// Selector and structured records have other columns, in csv a new header row starts each block of records
+/// with other columns and in table each block is printed as its own table.
+pub struct FormatSink{
+    name: String,
+    out: Box<dyn Write + Send>,
+    format: OutputFormat,
+    /// Stdout closed by the reader, like `| head`, the next records are dropped
+    closed: bool,
+    json_count: usize,
+    csv_headers: Option<Vec<String>>,
+    table_blocks: Vec<(Vec<String>, Vec<Vec<String>>)>
+}
+
+impl FormatSink{
+    fn new(name: String, out: Box<dyn Write + Send>, format: OutputFormat) -> FormatSink{
+        return FormatSink{name, out, format, closed: false, json_count: 0, csv_headers: None, table_blocks: Vec::new()};
+    }
+
+    pub fn stdout(format: OutputFormat) -> FormatSink{
+        return FormatSink::new(String::from("stdout"), Box::new(io::stdout()), format);
+    }
+
+    /// A jsonl file gets the records of every run appended, a file of another format is replaced
+    /// as it can't be extended
+    pub fn file(path: &str, format: OutputFormat) -> Result<FormatSink>{
+        let file = match format {
+            OutputFormat::Jsonl => OpenOptions::new().create(true).append(true).open(path)?,
+            _ => File::create(path)?
+        };
+        return Ok(FormatSink::new(format!("{:?} '{}'", format, path).to_lowercase(), Box::new(BufWriter::new(file)), format));
+    }
+
+    fn format_records(&mut self, records: &PairRecords) -> Result<Vec<u8>>{
+        let mut buf: Vec<u8> = Vec::new();
+        match self.format {
+            OutputFormat::Jsonl => for line in json_lines(records)? {
+                writeln!(buf, "{}", line)?;
+            },
+            OutputFormat::Json => for line in json_lines(records)? {
+                buf.extend_from_slice(if self.json_count == 0 { b"[\n  " } else { b",\n  " });
+                buf.extend_from_slice(line.as_bytes());
+                self.json_count += 1;
+            },
+            OutputFormat::Csv => {
+                let (headers, rows) = text_rows(records, self.format);
+                let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(&mut buf);
+                if self.csv_headers.as_ref() != Some(&headers) {
+                    wtr.write_record(&headers)?;
+                    self.csv_headers = Some(headers);
+                }
+                for row in rows {
+                    wtr.write_record(&row)?;
+                }
+                wtr.flush()?;
+            },
+            OutputFormat::Table => {
+                let (headers, rows) = text_rows(records, self.format);
+                match self.table_blocks.last_mut() {
+                    Some((last_headers, last_rows)) if *last_headers == headers => last_rows.extend(rows),
+                    _ => self.table_blocks.push((headers, rows))
+                }
+            }
+        }
+        return Ok(buf);
+    }
+
+    /// A closed stdout isn't an error, the run goes on for the other sinks
+    fn write_out(&mut self, buf: &[u8]) -> Result<()>{
+        if self.closed || buf.is_empty() {
+            return Ok(());
+        }
+        return match self.out.write_all(buf).and_then(|_| self.out.flush()) {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                self.closed = true;
+                Ok(())
+            },
+            other => Ok(other?)
+        };
+    }
+}
+
+impl RecordSink for FormatSink{
+    fn name(&self) -> String{
+        return self.name.clone();
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<SaveOutcome>{
+        if !batch.records.is_empty() {
+            let buf = self.format_records(batch.records)?;
+            self.write_out(&buf)?;
+        }
+        return Ok(SaveOutcome::all_new(batch.records.len()));
+    }
+
+    fn finish(&mut self, _ended_at: u64) -> Result<()>{
+        let mut buf: Vec<u8> = Vec::new();
+        match self.format {
+            OutputFormat::Json if self.json_count == 0 => writeln!(buf, "[]")?,
+            OutputFormat::Json => writeln!(buf, "\n]")?,
+            OutputFormat::Table => for (i, (headers, rows)) in self.table_blocks.iter().enumerate() {
+                if i > 0 {
+                    writeln!(buf)?;
+                }
+                write_text_table(&mut buf, headers, rows)?;
+            },
+            _ => {}
+        }
+        return self.write_out(&buf);
+    }
+}
+
+/// The sinks of the config outputs, in the `Config::output_specs` order
+pub fn sinks_from_config(config: &Config) -> Result<Vec<Box<dyn RecordSink>>>{
+    let mut sinks: Vec<Box<dyn RecordSink>> = Vec::new();
+    for spec in config.output_specs() {
+        let sink: Box<dyn RecordSink> = match spec {
+            OutputSpec::Sqlite{path} => Box::new(SqliteSink::new(&path, &config.storage, config.print_db_stats, &config.retention)?),
+            OutputSpec::Csv => Box::new(CsvSink::new(config.csv.clone())),
+            OutputSpec::Jsonl{path} => Box::new(FormatSink::file(&path, OutputFormat::Jsonl)?),
+            OutputSpec::File{path, format} => Box::new(FormatSink::file(&path, format)?),
+            OutputSpec::Stdout{format} => Box::new(FormatSink::stdout(format))
+        };
+        sinks.push(sink);
+    }
+    return Ok(sinks);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::scrap_utils::{FieldValue, StructuredRecord};
+
+    #[test]
+    fn test_jsonl_sink_appends_records() {
+        let path = std::env::temp_dir().join("web_scrap_cli_test_sink.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let url_selector = UrlSelectorPair::new("https://www.cnews.fr/", "h3");
+        let selector_records = PairRecords::Selector(vec![SelectorRecord::new(100, String::from("https://www.cnews.fr/"), String::from("h3"),
+                                                                              String::from("Headline"), String::from("text"))]);
+        let structured_records = PairRecords::Structured(vec![StructuredRecord{timestamp: 100, url: String::from("https://www.cnews.fr/"),
+            selector: String::from("article"), host: String::from("www.cnews.fr"),
+            fields: vec![(String::from("title"), FieldValue::Text(String::from("Title"))), (String::from("views"), FieldValue::Null)]}]);
+
+        for records in [&selector_records, &structured_records] {
+            let mut sink = FormatSink::file(path.to_str().unwrap(), OutputFormat::Jsonl).unwrap();
+            let batch = RecordBatch{run_id: 1, index: 0, url_selector: &url_selector, table: "selector_record",
+                                    table_options: &TableOptions::default(), records};
+            assert_eq!(sink.write(&batch).unwrap(), SaveOutcome::all_new(1));
+            sink.finish(0).unwrap();
+        }
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["Content"], "Headline");
+        assert_eq!(lines[1]["title"], "Title");
+        assert!(lines[1]["views"].is_null());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_format_sink_formats() {
+        let path = std::env::temp_dir().join("web_scrap_cli_test_sink_formats.txt");
+        let url_selector = UrlSelectorPair::new("https://www.cnews.fr/", "h3");
+        let headline = |content: &str| SelectorRecord::new(100, String::from("https://www.cnews.fr/"), String::from("h3"),
+                                                           String::from(content), String::from("text"));
+        let batches = [PairRecords::Selector(vec![headline("Headline 1")]), PairRecords::Selector(vec![headline("Headline 2")]),
+                       PairRecords::Structured(vec![StructuredRecord{timestamp: 100, url: String::from("https://www.cnews.fr/"),
+                           selector: String::from("article"), host: String::from("www.cnews.fr"),
+                           fields: vec![(String::from("title"), FieldValue::Text(String::from("Title")))]}])];
+        let write_all = |format: OutputFormat| {
+            let mut sink = FormatSink::file(path.to_str().unwrap(), format).unwrap();
+            for records in &batches {
+                let batch = RecordBatch{run_id: 1, index: 0, url_selector: &url_selector, table: "selector_record",
+                                        table_options: &TableOptions::default(), records};
+                sink.write(&batch).unwrap();
+            }
+            sink.finish(0).unwrap();
+            std::fs::read_to_string(&path).unwrap()
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&write_all(OutputFormat::Json)).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 3);
+        assert_eq!(json[1]["Content"], "Headline 2");
+        assert_eq!(json[2]["title"], "Title");
+
+        assert_eq!(write_all(OutputFormat::Csv), "Timestamp,Url,Selector,Content,Host,Extract\n\
+                                                  100,https://www.cnews.fr/,h3,Headline 1,www.cnews.fr,text\n\
+                                                  100,https://www.cnews.fr/,h3,Headline 2,www.cnews.fr,text\n\
+                                                  Timestamp,Url,Selector,Host,title\n\
+                                                  100,https://www.cnews.fr/,article,www.cnews.fr,Title\n");
+
+        let table = write_all(OutputFormat::Table);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[2], "100        www.cnews.fr  h3        Headline 2");
+        assert_eq!(lines[4], "Timestamp  Host          Selector  title");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_csv_sink_append_and_layout() {
+        let dir = std::env::temp_dir().join("web_scrap_cli_test_csv_sink");
+        let _ = std::fs::remove_dir_all(&dir);
+        let settings = CsvSettings{dir: dir.to_str().unwrap().to_string(), name: String::from("{year}/{month}/{day}/{host}.csv"),
+                                   delimiter: b';', append: true, ..CsvSettings::default()};
+        let url_selector = UrlSelectorPair::new("https://www.cnews.fr/", "h3");
+        let records = PairRecords::Selector(vec![SelectorRecord::new(100, String::from("https://www.cnews.fr/"), String::from("h3"),
+                                                                     String::from("Headline; 1"), String::from("text"))]);
+        let batch = RecordBatch{run_id: 1, index: 0, url_selector: &url_selector, table: "selector_record",
+                                table_options: &TableOptions::default(), records: &records};
+        // two runs of the same day append to the same file, the header is written once
+        for _ in 0..2 {
+            let mut sink = CsvSink::new(settings.clone());
+            sink.start_run(1709251200, "").unwrap();
+            sink.write(&batch).unwrap();
+            sink.finish(0).unwrap();
+        }
+        let path = dir.join("2024/03/01/www.cnews.fr.csv");
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().collect::<Vec<&str>>(), vec!["Timestamp;Url;Selector;Content;Host;Extract",
+            "100;https://www.cnews.fr/;h3;\"Headline; 1\";www.cnews.fr;text", "100;https://www.cnews.fr/;h3;\"Headline; 1\";www.cnews.fr;text"]);
+
+        // structured records don't have the same columns
+        let structured = PairRecords::Structured(vec![StructuredRecord{timestamp: 100, url: String::from("https://www.cnews.fr/"),
+            selector: String::from("article"), host: String::from("www.cnews.fr"), fields: Vec::new()}]);
+        let mut sink = CsvSink::new(settings);
+        sink.start_run(1709251200, "").unwrap();
+        assert!(sink.write(&RecordBatch{records: &structured, ..batch}).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_output_specs() {
+        let mut config = Config::new(&[UrlSelectorPair::new("https://www.cnews.fr/", "h3")], &true, &Some(String::from("records.db")), "selector_record", &false);
+        let stdout = OutputSpec::Stdout{format: OutputFormat::Jsonl};
+        config.outputs = vec![OutputSpec::Sqlite{path: String::from("records.db")}, stdout.clone()];
+        assert_eq!(config.output_specs(), vec![OutputSpec::Sqlite{path: String::from("records.db")}, OutputSpec::Csv, stdout]);
+    }
+}
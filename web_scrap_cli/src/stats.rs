@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+
+use clap::ArgEnum;
+use rusqlite::Connection;
+use serde::Serialize;
+
+use super::db_utils::{get_col_names, get_db_table_names, get_row_count, quote_ident};
+use super::errors::Result;
+use super::migrations::record_tables;
+use super::query::civil_from_timestamp;
+
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat{
+    Text,
+    Json
+}
+
+/// What the `stats` command and `--print-db-stats` report about a database
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DbStats{
+    pub path: String,
+    /// Size of the database file with its WAL file, None for a database without a file
+    pub file_size: Option<u64>,
+    pub tables: Vec<TableStats>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TableStats{
+    pub name: String,
+    /// "selector" or "structured", None for a table created outside of the scraper
+    pub kind: Option<String>,
+    pub columns: Vec<String>,
+    pub records: u64,
+    pub first_timestamp: Option<u64>,
+    pub last_timestamp: Option<u64>,
+    pub distinct_selectors: u64,
+    /// In characters, only the selector record tables have a content
+    pub avg_content_length: Option<f64>,
+    /// Most records first
+    pub hosts: Vec<HostStats>,
+    /// UTC days, oldest first
+    pub records_per_day: Vec<DayStats>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HostStats{
+    pub host: String,
+    pub records: u64,
+    pub first_timestamp: Option<u64>,
+    pub last_timestamp: Option<u64>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DayStats{
+    /// YYYY-MM-DD
+    pub day: String,
+    pub records: u64
+}
+
+/// Size of a database file with its WAL file, None for a database without a file
+pub fn db_file_size(path: &str) -> Option<u64>{
+    return fs::metadata(path).ok().map(|m| m.len() + fs::metadata(format!("{}-wal", path)).map(|w| w.len()).unwrap_or(0));
+}
+
+/// Reads the stats of every record table, each one is a few aggregate queries without loading the records
+pub fn db_stats(conn: &Connection, path: &str) -> Result<DbStats>{
+    let file_size = db_file_size(path);
+    let kinds: HashMap<String, String> = record_tables(conn)?.into_iter().collect();
+    let mut tables = Vec::new();
+    for table in get_db_table_names(conn)? {
+        let kind = kinds.get(&table).cloned();
+        tables.push(table_stats(conn, &table, kind)?);
+    }
+    return Ok(DbStats{path: path.to_string(), file_size, tables});
+}
+
+fn table_stats(conn: &Connection, table: &str, kind: Option<String>) -> Result<TableStats>{
+    let columns = get_col_names(conn, table)?;
+    let has = |column: &str| columns.iter().any(|c| c == column);
+    let quoted = quote_ident(table);
+    let mut stats = TableStats{name: table.to_string(), kind, columns: columns.clone(), records: 0, first_timestamp: None, last_timestamp: None,
+                               distinct_selectors: 0, avg_content_length: None, hosts: Vec::new(), records_per_day: Vec::new()};
+    stats.records = get_row_count(conn, table)? as u64;
+    if has("timestamp") {
+        (stats.first_timestamp, stats.last_timestamp) = conn.query_row(&format!("SELECT MIN(timestamp), MAX(timestamp) FROM {}", quoted), [],
+                                                                       |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut stmt = conn.prepare(&format!("SELECT date(timestamp, 'unixepoch') AS day, COUNT(*) FROM {} GROUP BY day ORDER BY day", quoted))?;
+        let rows = stmt.query_map([], |row| Ok(DayStats{day: row.get::<_, Option<String>>(0)?.unwrap_or_default(), records: row.get(1)?}))?;
+        for row in rows {
+            stats.records_per_day.push(row?);
+        }
+    }
+    if has("selector") {
+        stats.distinct_selectors = conn.query_row(&format!("SELECT COUNT(DISTINCT selector) FROM {}", quoted), [], |row| row.get(0))?;
+    }
+    if has("content") {
+        stats.avg_content_length = conn.query_row(&format!("SELECT AVG(length(content)) FROM {}", quoted), [], |row| row.get(0))?;
+    }
+    if has("host") && has("timestamp") {
+        let mut stmt = conn.prepare(&format!("SELECT host, COUNT(*) AS records, MIN(timestamp), MAX(timestamp) FROM {}
+                                              GROUP BY host ORDER BY records DESC, host", quoted))?;
+        let rows = stmt.query_map([], |row| Ok(HostStats{host: row.get(0)?, records: row.get(1)?, first_timestamp: row.get(2)?,
+                                                         last_timestamp: row.get(3)?}))?;
+        for row in rows {
+            stats.hosts.push(row?);
+        }
+    }
+    return Ok(stats);
+}
+
+/// "YYYY-MM-DD HH:MM:SS" in UTC
+fn format_timestamp(timestamp: u64) -> String{
+    let (year, month, day) = civil_from_timestamp(timestamp);
+    let secs = timestamp % 86400;
+    return format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, secs / 3600, secs % 3600 / 60, secs % 60);
+}
+
+pub fn format_size(bytes: u64) -> String{
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    return if unit == 0 { format!("{} B", bytes) } else { format!("{:.1} {}", size, units[unit]) };
+}
+
+impl DbStats{
+    pub fn write<W: Write>(&self, out: &mut W, format: ReportFormat) -> Result<()>{
+        match format {
+            ReportFormat::Text => self.write_text(out)?,
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut *out, self).map_err(io::Error::from)?;
+                writeln!(out)?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn write_text<W: Write>(&self, out: &mut W) -> io::Result<()>{
+        writeln!(out, "--------------\nDatabase Stats: '{}'{}", self.path,
+                 self.file_size.map(|s| format!(", {}", format_size(s))).unwrap_or_default())?;
+        for table in &self.tables {
+            let kind = table.kind.as_deref().map(|k| format!(" ({})", k)).unwrap_or_default();
+            writeln!(out, ">> '{}' table{} : {} records", table.name, kind, table.records)?;
+            writeln!(out, "Columns : {:?}", table.columns)?;
+            if let (Some(first), Some(last)) = (table.first_timestamp, table.last_timestamp) {
+                writeln!(out, "From {} to {} UTC", format_timestamp(first), format_timestamp(last))?;
+            }
+            let mut details = format!("{} distinct selectors", table.distinct_selectors);
+            if let Some(length) = table.avg_content_length {
+                details.push_str(&format!(", average content length {:.1}", length));
+            }
+            writeln!(out, "{}", details)?;
+            if !table.hosts.is_empty() {
+                writeln!(out, "Hosts :")?;
+                for host in &table.hosts {
+                    writeln!(out, "  - {} : {} records", host.host, host.records)?;
+                }
+            }
+            if !table.records_per_day.is_empty() {
+                writeln!(out, "Records per day :")?;
+                for day in &table.records_per_day {
+                    writeln!(out, "  - {} : {}", day.day, day.records)?;
+                }
+            }
+        }
+        writeln!(out, "--------------")?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::config::TableOptions;
+    use super::super::db_utils::save_selector_records_to_db;
+    use super::super::migrations::migrate;
+    use super::super::scrap_utils::SelectorRecord;
+
+    #[test]
+    fn test_db_stats() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let record = |timestamp: u64, url: &str, selector: &str, content: &str| SelectorRecord::new(timestamp, url.to_string(),
+            selector.to_string(), content.to_string(), String::from("text"));
+        save_selector_records_to_db(&mut conn, "selector_record", &[record(1709251200, "https://www.cnews.fr/", "h3", "abcd"),
+                                                                    record(1709251300, "https://www.cnews.fr/sport", "h2", "ab"),
+                                                                    record(1709337600, "https://www.google.fr/", "h3", "abcdef")],
+                                    &TableOptions::default(), None).unwrap();
+
+        let stats = db_stats(&conn, ":memory:").unwrap();
+        assert_eq!(stats.file_size, None);
+        assert_eq!(stats.tables.len(), 1);
+        let table = &stats.tables[0];
+        assert_eq!((table.kind.as_deref(), table.records, table.distinct_selectors), (Some("selector"), 3, 2));
+        assert_eq!((table.first_timestamp, table.last_timestamp), (Some(1709251200), Some(1709337600)));
+        assert_eq!(table.avg_content_length, Some(4.0));
+        assert_eq!(table.hosts[0], HostStats{host: String::from("www.cnews.fr"), records: 2, first_timestamp: Some(1709251200),
+                                             last_timestamp: Some(1709251300)});
+        assert_eq!(table.records_per_day, vec![DayStats{day: String::from("2024-03-01"), records: 2},
+                                               DayStats{day: String::from("2024-03-02"), records: 1}]);
+
+        let mut out: Vec<u8> = Vec::new();
+        stats.write(&mut out, ReportFormat::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["tables"][0]["hosts"][1]["host"], "www.google.fr");
+        let mut out: Vec<u8> = Vec::new();
+        stats.write(&mut out, ReportFormat::Text).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("From 2024-03-01 00:00:00 to 2024-03-02 00:00:00 UTC"));
+    }
+}
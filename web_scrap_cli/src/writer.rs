@@ -0,0 +1,300 @@
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::*;
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+
+use super::config::{Config, UrlSelectorPair};
+use super::db_utils::SaveOutcome;
+use super::errors::{Result, ScrapeError};
+use super::file_utils::get_timestamp_now;
+use super::sinks::{RecordBatch, RecordSink};
+use super::{handle_records, PageResult, RunSummary};
+
+/// What the request tasks send to the writer thread
+pub enum WriterMessage{
+    /// A completed request, `index` is the position of its first pair in the run
+    Page{index: usize, url_selectors: Vec<UrlSelectorPair>, page: PageResult},
+    /// A request task that panicked or was cancelled
+    Failed{url_selectors: Vec<UrlSelectorPair>, error: ScrapeError},
+    /// Sent at the flush interval
+    Flush
+}
+
+/// The thread owning the sinks, it writes the pages in the order their requests complete
+/// and returns the summary of the run once every sender is dropped
+pub struct Writer{
+    pub sender: Sender<WriterMessage>,
+    thread: JoinHandle<Result<RunSummary>>
+}
+
+impl Writer{
+    /// The sinks must have started the run, they are finished by the thread
+    pub fn spawn(config: Arc<Config>, sinks: Vec<Box<dyn RecordSink>>, run_id: i64) -> Result<Writer>{
+        let (sender, receiver) = mpsc::channel(config.writer.queue_size);
+        let thread = thread::Builder::new().name(String::from("writer"))
+                                           .spawn(move || write_pages(receiver, &config, sinks, run_id))?;
+        return Ok(Writer{sender, thread});
+    }
+
+    /// Waits for the pages still queued, the other senders must be dropped first
+    pub async fn finish(self) -> Result<RunSummary>{
+        drop(self.sender);
+        let thread = self.thread;
+        return match tokio::task::spawn_blocking(move || thread.join()).await? {
+            Ok(result) => result,
+            Err(_) => Err(ScrapeError::Task(String::from("the writer thread panicked")))
+        };
+    }
+}
+
+/// Asks the writer to commit at each interval, a full queue skips a tick as the writer is busy anyway
+pub async fn flush_ticker(sender: Sender<WriterMessage>, interval: Duration){
+    let mut ticks = tokio::time::interval(interval);
+    // the first tick completes at once
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        if let Err(TrySendError::Closed(_)) = sender.try_send(WriterMessage::Flush) {
+            return;
+        }
+    }
+}
+
+fn write_pages(mut receiver: Receiver<WriterMessage>, config: &Config, mut sinks: Vec<Box<dyn RecordSink>>, run_id: i64) -> Result<RunSummary>{
+    let mut summary = RunSummary::default();
+    // records written since the last commit
+    let mut pending = 0;
+    let mut first_error = None;
+    while let Some(message) = receiver.blocking_recv() {
+        let flush_now = match message {
+            WriterMessage::Page{index, url_selectors, page} => {
+                pending += write_page(config, &mut sinks, run_id, index, url_selectors, page, &mut summary);
+                false
+            },
+            WriterMessage::Failed{url_selectors, error} => {
+                summary.add_failure(url_selectors, error);
+                false
+            },
+            WriterMessage::Flush => pending > 0
+        };
+        if flush_now || pending >= config.writer.batch_size {
+            if let Err(e) = flush(&mut sinks) {
+                // the request tasks see the closed queue and stop, the sinks are still finished so that the run gets its end
+                first_error = Some(e);
+                receiver.close();
+                break;
+            }
+            pending = 0;
+        }
+    }
+
+    let ended_at = get_timestamp_now();
+    for sink in sinks.iter_mut() {
+        if let Err(e) = sink.finish(ended_at) {
+            error!("Failed to finish {} : {}", sink.name(), e);
+            first_error.get_or_insert(e);
+        }
+    }
+    return match first_error {
+        Some(e) => Err(e),
+        None => Ok(summary)
+    };
+}
+
+/// A failed commit loses the records of the batch, it stops the run
+fn flush(sinks: &mut [Box<dyn RecordSink>]) -> Result<()>{
+    debug!("Committing the records of the batch");
+    let mut first_error = None;
+    for sink in sinks.iter_mut() {
+        if let Err(e) = sink.flush() {
+            error!("Failed to commit to {} : {}", sink.name(), e);
+            first_error.get_or_insert(e);
+        }
+    }
+    return match first_error {
+        Some(e) => Err(e),
+        None => Ok(())
+    };
+}
+
+/// Writes the records of each pair of a page and returns their number
+fn write_page(config: &Config, sinks: &mut [Box<dyn RecordSink>], run_id: i64, index: usize, url_selectors: Vec<UrlSelectorPair>,
+              page: PageResult, summary: &mut RunSummary) -> usize{
+    // a failed page or pair is reported at the end, it must not prevent saving the others
+    let (fetch_info, page_result) = page;
+    if let Some(info) = &fetch_info {
+        if let Err(e) = sinks.iter_mut().try_for_each(|sink| sink.page_fetched(info)) {
+            summary.add_failure(url_selectors, e);
+            return 0;
+        }
+    }
+    let selector_results = match page_result {
+        Ok((selector_results, filter_stats)) => {
+            summary.filter_stats.merge(&filter_stats);
+            selector_results
+        },
+        Err(e) => {
+            summary.add_failure(url_selectors, e);
+            return 0;
+        }
+    };
+    let mut written = 0;
+    for (i, (selector_result, url_selector)) in selector_results.into_iter().zip(url_selectors).enumerate(){
+        let handled = match selector_result {
+            Ok(records) if !records.is_empty() => {
+                written += records.len();
+                let table = url_selector.table.as_deref().unwrap_or(&config.table);
+                let batch = RecordBatch{run_id, index: index + i, url_selector: &url_selector, table,
+                                        table_options: config.storage.options_for(table), records: &records};
+                handle_records(sinks, &batch)
+            },
+            Ok(_) => Ok(SaveOutcome::default()),
+            Err(e) => Err(e),
+        };
+        match handled {
+            Ok(outcome) => summary.saved.add(outcome),
+            Err(error) => summary.add_failure(vec![url_selector], error)
+        }
+    }
+    return written;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::db_utils::{get_row_count, open_existing_db};
+    use super::super::filters::FilterStats;
+    use super::super::scrap_utils::{PairRecords, SelectorRecord};
+    use super::super::sinks::SqliteSink;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    /// Keeps the number of records of each flush
+    struct CountingSink{
+        pending: usize,
+        flushes: Arc<Mutex<Vec<usize>>>
+    }
+
+    impl RecordSink for CountingSink{
+        fn name(&self) -> String{
+            return String::from("counting");
+        }
+
+        fn write(&mut self, batch: &RecordBatch) -> Result<SaveOutcome>{
+            self.pending += batch.records.len();
+            return Ok(SaveOutcome::all_new(batch.records.len()));
+        }
+
+        fn flush(&mut self) -> Result<()>{
+            self.flushes.lock().unwrap().push(self.pending);
+            self.pending = 0;
+            return Ok(());
+        }
+
+        fn finish(&mut self, _ended_at: u64) -> Result<()>{
+            return self.flush();
+        }
+    }
+
+    /// A sink whose commits fail, it keeps whether it was finished
+    struct FailingSink{
+        finished: Arc<Mutex<bool>>
+    }
+
+    impl RecordSink for FailingSink{
+        fn name(&self) -> String{
+            return String::from("failing");
+        }
+
+        fn write(&mut self, batch: &RecordBatch) -> Result<SaveOutcome>{
+            return Ok(SaveOutcome::all_new(batch.records.len()));
+        }
+
+        fn flush(&mut self) -> Result<()>{
+            return Err(ScrapeError::Task(String::from("disk full")));
+        }
+
+        fn finish(&mut self, _ended_at: u64) -> Result<()>{
+            *self.finished.lock().unwrap() = true;
+            return Ok(());
+        }
+    }
+
+    fn page(url: &str, count: usize) -> WriterMessage{
+        let records = (0..count).map(|i| SelectorRecord::new(100, url.to_string(), String::from("h3"), format!("Headline {}", i), String::from("text")))
+                                .collect();
+        return WriterMessage::Page{index: 0, url_selectors: vec![UrlSelectorPair::new(url, "h3")],
+                                   page: (None, Ok((vec![Ok(PairRecords::Selector(records))], FilterStats::default())))};
+    }
+
+    #[tokio::test]
+    async fn test_writer_commits_batches() {
+        let mut config = Config::new(&[], &false, &None, "news", &false);
+        config.writer.batch_size = 5;
+        let flushes = Arc::new(Mutex::new(Vec::new()));
+        let sink = CountingSink{pending: 0, flushes: flushes.clone()};
+        let writer = Writer::spawn(Arc::new(config), vec![Box::new(sink)], 1).unwrap();
+
+        for count in [3, 3, 1] {
+            writer.sender.send(page("https://www.cnews.fr/", count)).await.unwrap();
+        }
+        writer.sender.send(WriterMessage::Flush).await.unwrap();
+        writer.sender.send(page("https://www.cnews.fr/", 2)).await.unwrap();
+        writer.sender.send(WriterMessage::Failed{url_selectors: vec![UrlSelectorPair::new("https://www.france24.com/fr/", "a")],
+                                                 error: ScrapeError::Task(String::from("cancelled"))}).await.unwrap();
+        let summary = writer.finish().await.unwrap();
+        assert_eq!(summary.saved.new, 9);
+        assert_eq!(summary.failures.len(), 1);
+        assert_eq!(*flushes.lock().unwrap(), vec![6, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_writer_finishes_sinks_after_failed_flush() {
+        let mut config = Config::new(&[], &false, &None, "news", &false);
+        config.writer.batch_size = 1;
+        let finished = Arc::new(Mutex::new(false));
+        let writer = Writer::spawn(Arc::new(config), vec![Box::new(FailingSink{finished: finished.clone()})], 1).unwrap();
+
+        writer.sender.send(page("https://www.cnews.fr/", 2)).await.unwrap();
+        // the queue is closed once the flush failed
+        writer.sender.closed().await;
+        assert!(writer.sender.send(page("https://www.cnews.fr/", 1)).await.is_err());
+        assert!(matches!(writer.finish().await, Err(ScrapeError::Task(_))));
+        assert!(*finished.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_writer_flush_visible_to_readers() {
+        let path = std::env::temp_dir().join("web_scrap_cli_test_writer.db");
+        let path = path.to_str().unwrap();
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+        let config = Config::new(&[], &false, &Some(path.to_string()), "news", &false);
+        let mut sink = SqliteSink::new(path, &config.storage, false, &config.retention).unwrap();
+        let run_id = sink.start_run(100, "hash").unwrap().unwrap();
+        let writer = Writer::spawn(Arc::new(config), vec![Box::new(sink)], run_id).unwrap();
+        let reader = open_existing_db(path, false).unwrap();
+        // the table is created by the first batch, it doesn't exist before its commit
+        let committed = || get_row_count(&reader, "news").unwrap_or(0);
+
+        writer.sender.send(page("https://www.cnews.fr/", 3)).await.unwrap();
+        writer.sender.send(page("https://www.france24.com/fr/", 2)).await.unwrap();
+        // below the batch size, the records wait for a flush
+        assert_eq!(committed(), 0);
+        writer.sender.send(WriterMessage::Flush).await.unwrap();
+        let start = Instant::now();
+        while committed() < 5 && start.elapsed() < Duration::from_secs(5) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // read while the run is still going
+        assert_eq!(committed(), 5);
+
+        let summary = writer.finish().await.unwrap();
+        assert_eq!(summary.saved.new, 5);
+        let ended_at: Option<u64> = reader.query_row("SELECT ended_at FROM runs WHERE id = ?1", [run_id], |row| row.get(0)).unwrap();
+        assert!(ended_at.is_some());
+    }
+}
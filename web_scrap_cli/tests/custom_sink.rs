@@ -0,0 +1,122 @@
+#![allow(clippy::needless_return)]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use web_scrap_cli::config::{HostLimits, UrlSelectorPair};
+use web_scrap_cli::errors::{Result, ScrapeError};
+use web_scrap_cli::run_with_sinks;
+use web_scrap_cli::scrap_utils::{FetchInfo, PairRecords};
+use web_scrap_cli::sinks::{RecordBatch, RecordSink};
+use web_scrap_cli::{Config, SaveOutcome};
+
+const PAGE: &str = "<html><body><h3>Élections européennes : les résultats</h3><h3>Le budget voté à l'Assemblée</h3></body></html>";
+
+/// Keeps the contents it receives and the calls made by the writer
+#[derive(Default)]
+struct MemorySink{
+    contents: Arc<Mutex<Vec<String>>>,
+    calls: Arc<Mutex<Vec<&'static str>>>
+}
+
+impl RecordSink for MemorySink{
+    fn name(&self) -> String{
+        return String::from("memory");
+    }
+
+    fn start_run(&mut self, _started_at: u64, _config_hash: &str) -> Result<Option<i64>>{
+        self.calls.lock().unwrap().push("start_run");
+        return Ok(Some(7));
+    }
+
+    fn page_fetched(&mut self, fetch: &FetchInfo) -> Result<()>{
+        assert_eq!(fetch.status_code, Some(200));
+        self.calls.lock().unwrap().push("page_fetched");
+        return Ok(());
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<SaveOutcome>{
+        assert_eq!(batch.run_id, 7);
+        if let PairRecords::Selector(records) = batch.records {
+            self.contents.lock().unwrap().extend(records.iter().map(|r| r.content.clone()));
+        }
+        self.calls.lock().unwrap().push("write");
+        return Ok(SaveOutcome::all_new(batch.records.len()));
+    }
+
+    fn finish(&mut self, _ended_at: u64) -> Result<()>{
+        self.calls.lock().unwrap().push("finish");
+        return Ok(());
+    }
+}
+
+/// A sink whose commits fail
+struct FailingSink;
+
+impl RecordSink for FailingSink{
+    fn name(&self) -> String{
+        return String::from("failing");
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<SaveOutcome>{
+        return Ok(SaveOutcome::all_new(batch.records.len()));
+    }
+
+    fn flush(&mut self) -> Result<()>{
+        return Err(ScrapeError::Task(String::from("disk full")));
+    }
+}
+
+/// Serves the page to every request until the test ends, returns its url and the number of requests
+async fn serve_page() -> (String, Arc<AtomicUsize>){
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            let mut request = [0; 4096];
+            let _ = socket.read(&mut request).await;
+            let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                   PAGE.len(), PAGE);
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    });
+    return (format!("http://{}/", address), requests);
+}
+
+#[tokio::test]
+async fn test_scrape_through_custom_sink() {
+    let (url, _) = serve_page().await;
+    let mut config = Config::new(&[UrlSelectorPair::new(&url, "h3")], &false, &None, "news", &false);
+    config.robots.enabled = false;
+    let sink = MemorySink::default();
+    let (contents, calls) = (sink.contents.clone(), sink.calls.clone());
+
+    let summary = run_with_sinks(config, vec![Box::new(sink)]).await.unwrap();
+    assert_eq!(summary.failed_count(), 0);
+    assert_eq!(summary.saved.new, 2);
+    assert_eq!(*contents.lock().unwrap(), vec!["Élections européennes : les résultats", "Le budget voté à l'Assemblée"]);
+    assert_eq!(*calls.lock().unwrap(), vec!["start_run", "page_fetched", "write", "finish"]);
+}
+
+#[tokio::test]
+async fn test_failed_commit_stops_requests() {
+    let (url, requests) = serve_page().await;
+    let url_selectors: Vec<UrlSelectorPair> = (0..4).map(|i| UrlSelectorPair::new(&format!("{}page{}", url, i), "h3")).collect();
+    let mut config = Config::new(&url_selectors, &false, &None, "news", &false);
+    config.robots.enabled = false;
+    config.politeness.default_host_limits = HostLimits{max_in_flight: 1, min_delay: Duration::from_millis(500)};
+    config.writer.batch_size = 1;
+
+    // the first page is committed long before the next request may start
+    let result = run_with_sinks(config, vec![Box::new(FailingSink)]).await;
+    assert!(matches!(result, Err(ScrapeError::Task(_))));
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}